@@ -0,0 +1,161 @@
+// Runs the servers without a window, for line PCs with no display.
+// Starts every server with autostart set, and stops them cleanly on Ctrl-C or SIGTERM.
+use std::collections::HashMap;
+use std::sync::{Arc, mpsc};
+use std::time::Duration;
+use tokio::sync::Notify;
+use s7_event_monitor::comms_tcon::*;
+use s7_event_monitor::config_store::{config_path, set_config_path, SERVER_CONFIG};
+use s7_event_monitor::constants::*;
+use s7_event_monitor::server_id::ServerId;
+use s7_event_monitor::sql::{connect_to_db, db_path, set_db_path};
+use s7_event_monitor::utils::*;
+use s7_event_monitor::xmlhandling::{load_config, watch_config};
+
+const USAGE: &str = "\
+Usage: s7-event-monitor-headless [options]
+
+Options:
+    --config <path>      Server config (default config.xml)
+    --db <path>          Event database (default event_data.db)
+    --log-level <level>  error, warning, info or debug (default info)
+    -h, --help           Show this message";
+
+// Longest wait for the servers to stop before exiting anyway
+const STOP_TIMEOUT_SECS: u64 = 30;
+
+struct Options {
+    config: Option<String>,
+    db: Option<String>,
+    log_level: LogLevel,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut options = Options {
+        config: None,
+        db: None,
+        log_level: LogLevel::Info,
+    };
+    while let Some(arg) = args.next() {
+        // Both "--db path" and "--db=path"
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) => (name.to_string(), Some(value.to_string())),
+            None => (arg.clone(), None),
+        };
+        let mut value = || inline_value.clone().or_else(|| args.next()).ok_or_else(|| format!("{} needs a value", name));
+        match name.as_str() {
+            "--config" => options.config = Some(value()?),
+            "--db" => options.db = Some(value()?),
+            "--log-level" => options.log_level = LogLevel::parse(&value()?).map_err(|e| e.to_string())?,
+            "-h" | "--help" => return Ok(None),
+            _ => return Err(format!("Unknown option {}", arg)),
+        }
+    }
+    Ok(Some(options))
+}
+
+#[tokio::main]
+async fn main() {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    set_log_level(options.log_level);
+    set_log_to_console(true);
+    if let Some(path) = options.config.as_deref() {
+        set_config_path(path);
+    }
+    if let Some(path) = options.db.as_deref() {
+        set_db_path(path);
+    }
+
+    // The window falls back to a default server, but here that would just sit there doing nothing
+    let config_file = config_path();
+    if let Err(e) = load_config(&config_file) {
+        log_error(&format!("Can't load {}: {}", config_file, e));
+        std::process::exit(1);
+    }
+    if let Err(e) = connect_to_db() {
+        log_error(&format!("Can't open database {}: {}", db_path(), e));
+        std::process::exit(1);
+    }
+    log(&format!("{} {} starting headless, config {}, database {}", APPNAME, APPVERSION, config_file, db_path()));
+
+    let (status_tx, status_rx) = mpsc::channel::<ServerStatusInfo>();
+    let (mut server_manager, command_tx) = ServerManager::new(status_tx, Arc::new(Notify::new()));
+    let manager = tokio::spawn(async move {
+        server_manager.process_commands().await;
+    });
+    let config_watcher = tokio::spawn(watch_config(config_file, command_tx.clone()));
+    std::thread::spawn(move || log_status_changes(status_rx));
+
+    let mut started = 0;
+    for server in SERVER_CONFIG.servers().iter().filter(|s| s.autostart) {
+        log(&format!("Auto-starting server {}: {}:{}", server.name, server.ip_address, server.port));
+        let _ = command_tx.send(ServerCommand::Start(server.id));
+        started += 1;
+    }
+    if started == 0 {
+        log_warn("No servers have autostart set, nothing will be logged until the config is changed.");
+    }
+
+    wait_for_stop_signal().await;
+    log("Stopping servers...");
+
+    // Let every server finish the packet it's storing, then let the manager run out of commands
+    config_watcher.abort();
+    let _ = command_tx.send(ServerCommand::StopAll);
+    drop(command_tx);
+    if tokio::time::timeout(Duration::from_secs(STOP_TIMEOUT_SECS), manager).await.is_err() {
+        log_warn("Servers didn't stop in time, exiting anyway.");
+    }
+    log("Stopped.");
+}
+
+async fn wait_for_stop_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(windows)]
+    {
+        use tokio::signal::windows::{ctrl_close, ctrl_shutdown};
+        let mut close = ctrl_close().expect("Failed to listen for console close");
+        let mut shutdown = ctrl_shutdown().expect("Failed to listen for system shutdown");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = close.recv() => {}
+            _ = shutdown.recv() => {}
+        }
+    }
+}
+
+// Nobody is watching the Connections tab, so log the changes it would show instead
+fn log_status_changes(status_rx: mpsc::Receiver<ServerStatusInfo>) {
+    let mut last: HashMap<ServerId, ServerStatusInfo> = HashMap::new();
+    for status in status_rx {
+        let name = SERVER_CONFIG.server(&status.server_id).map(|s| s.name).unwrap_or_else(|| status.server_id.to_string());
+        let previous = last.insert(status.server_id, status);
+        if status.is_connected != previous.is_some_and(|p| p.is_connected) {
+            log(&format!("{} {}", name, if status.is_connected { "connected" } else { "disconnected" }));
+        }
+        if status.is_connected && !status.is_alive && previous.is_some_and(|p| p.is_alive) {
+            log_warn(&format!("{} is connected but has stopped sending keepalives", name));
+        }
+        if status.bind_failed && !previous.is_some_and(|p| p.bind_failed) {
+            log_error(&format!("{} can't listen: {}", name, status.get_last_error()));
+        }
+    }
+}
@@ -0,0 +1,157 @@
+// Pretends to be a PLC sending events to one of the servers in config.xml, for developing
+// and demonstrating without a real PLC.
+use std::time::Duration;
+use s7_event_monitor::constants::DEFAULT_CONFIG_PATH;
+use s7_event_monitor::simulator::*;
+use s7_event_monitor::utils::{log, set_log_path, set_log_to_console};
+use s7_event_monitor::xmlhandling::load_config;
+
+// Kept apart from the monitor's log.txt, which is often in the same folder
+const LOG_PATH: &str = "simulator_log.txt";
+
+const USAGE: &str = "\
+Usage: s7-plc-simulator (--server <name> | --address <host:port>) [options]
+
+Where to send:
+    --server <name>           A server from the config. Packets are built the way it expects them.
+    --config <path>           Config to find --server in (default config.xml)
+    --address <host:port>     Connect here instead, with raw framing, no sequence numbers or timestamps
+                              and legacy responses. With --server, overrides just the address.
+
+What to send (random unless --script is given):
+    --script <path>           Follow a scripted scenario, see the README
+    --events-per-min <n>      General events, type 50 (default 6)
+    --alarms-per-min <n>      Alarms, type 9 (default 1)
+    --downtimes-per-hour <n>  Downtime start/end pairs, lasting 30 s to 10 min (default 2)
+    --seed <n>                Same seed, same random scenario
+    --keepalive-secs <n>      Keepalive interval, 0 for none (default 10)
+    --downtime-db <n>         DowntimeDBNum in the configuration packet (default 100)
+    --seconds-before-downtime <n>  SecondsBeforeDowntime in the configuration packet (default 120)
+    -h, --help                Show this message";
+
+#[derive(Default)]
+struct Options {
+    server: Option<String>,
+    config: Option<String>,
+    address: Option<String>,
+    script: Option<String>,
+    events_per_min: Option<f64>,
+    alarms_per_min: Option<f64>,
+    downtimes_per_hour: Option<f64>,
+    seed: Option<u64>,
+    keepalive_secs: Option<u64>,
+    downtime_db: Option<u32>,
+    seconds_before_downtime: Option<u32>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut options = Options::default();
+    while let Some(arg) = args.next() {
+        // Both "--seed 5" and "--seed=5"
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) => (name.to_string(), Some(value.to_string())),
+            None => (arg.clone(), None),
+        };
+        let mut value = || inline_value.clone().or_else(|| args.next()).ok_or_else(|| format!("{} needs a value", name));
+        match name.as_str() {
+            "--server" => options.server = Some(value()?),
+            "--config" => options.config = Some(value()?),
+            "--address" => options.address = Some(value()?),
+            "--script" => options.script = Some(value()?),
+            "--events-per-min" => options.events_per_min = number(&name, value()?)?,
+            "--alarms-per-min" => options.alarms_per_min = number(&name, value()?)?,
+            "--downtimes-per-hour" => options.downtimes_per_hour = number(&name, value()?)?,
+            "--seed" => options.seed = number(&name, value()?)?,
+            "--keepalive-secs" => options.keepalive_secs = number(&name, value()?)?,
+            "--downtime-db" => options.downtime_db = number(&name, value()?)?,
+            "--seconds-before-downtime" => options.seconds_before_downtime = number(&name, value()?)?,
+            "-h" | "--help" => return Ok(None),
+            _ => return Err(format!("Unknown option {}", arg)),
+        }
+    }
+    if options.server.is_none() && options.address.is_none() {
+        return Err("Give either --server or --address".to_string());
+    }
+    Ok(Some(options))
+}
+
+fn number<T: std::str::FromStr>(name: &str, value: String) -> Result<Option<T>, String> {
+    value.parse().map(Some).map_err(|_| format!("{} must be a number", name))
+}
+
+fn simulator_config(options: &Options) -> Result<SimulatorConfig, String> {
+    let mut config = match &options.server {
+        Some(name) => {
+            let path = options.config.as_deref().unwrap_or(DEFAULT_CONFIG_PATH);
+            let servers = load_config(path).map_err(|e| format!("Can't load {}: {}", path, e))?;
+            let server = servers.server.iter()
+                .find(|s| s.name == *name)
+                .ok_or_else(|| format!("{} has no server called {}", path, name))?;
+            SimulatorConfig::from_server(server)?
+        }
+        None => SimulatorConfig::new(""),
+    };
+    if let Some(address) = &options.address {
+        config.address = address.clone();
+    }
+    if let Some(secs) = options.keepalive_secs {
+        config.keepalive_interval = (secs > 0).then_some(Duration::from_secs(secs));
+    }
+    if let Some(db) = options.downtime_db {
+        config.plc_config.downtime_db = db;
+    }
+    if let Some(seconds) = options.seconds_before_downtime {
+        config.plc_config.seconds_before_downtime = seconds;
+    }
+    Ok(config)
+}
+
+fn scenario(options: &Options) -> Result<Box<dyn Scenario>, String> {
+    if let Some(path) = &options.script {
+        let script = std::fs::read_to_string(path).map_err(|e| format!("Can't read {}: {}", path, e))?;
+        return Ok(Box::new(ScriptScenario::parse(&script)?));
+    }
+    let seed = options.seed.unwrap_or_else(time_seed);
+    log(&format!("Random scenario, seed {}", seed));
+    Ok(Box::new(RandomScenario::new(
+        options.events_per_min.unwrap_or(6.0),
+        options.alarms_per_min.unwrap_or(1.0),
+        options.downtimes_per_hour.unwrap_or(2.0),
+        seed,
+    )))
+}
+
+#[tokio::main]
+async fn main() {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    set_log_path(LOG_PATH);
+    set_log_to_console(true);
+    let (config, mut scenario) = match simulator_config(&options).and_then(|c| Ok((c, scenario(&options)?))) {
+        Ok(setup) => setup,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    log(&format!("Simulating a PLC sending to {}, Ctrl-C to stop", config.address));
+    let mut simulator = Simulator::new(config);
+    tokio::select! {
+        _ = async {
+            simulator.run(scenario.as_mut()).await;
+            log("Scenario finished, sending keepalives until stopped");
+            simulator.idle().await;
+        } => {}
+        _ = tokio::signal::ctrl_c() => log("Stopped"),
+    }
+}
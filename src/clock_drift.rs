@@ -0,0 +1,32 @@
+use chrono::{DateTime, Local, NaiveDateTime};
+
+// Decides when a PLC's clock has drifted too far, so the warning is stored once when the
+// drift goes past the limit and not again until the clock has come back within it.
+pub struct DriftMonitor {
+    max_drift_ms: i64,
+    warned: bool,
+}
+
+impl DriftMonitor {
+    pub fn new(max_drift_ms: u64) -> Self {
+        DriftMonitor {
+            max_drift_ms: max_drift_ms as i64,
+            warned: false,
+        }
+    }
+
+    // Call with every reading. Returns true the first time the drift goes past the limit, either way.
+    pub fn check(&mut self, drift_ms: i64) -> bool {
+        let too_far = drift_ms.abs() > self.max_drift_ms;
+        let warn = too_far && !self.warned;
+        self.warned = too_far;
+        warn
+    }
+}
+
+// PLC time minus PC time, positive when the PLC is ahead. The PLC's clock is read as local time,
+// and compared with the middle of the read so the round trip doesn't count as drift.
+pub fn drift_ms(plc_time: NaiveDateTime, before: DateTime<Local>, after: DateTime<Local>) -> i64 {
+    let pc_time = before + (after - before) / 2;
+    plc_time.signed_duration_since(pc_time.naive_local()).num_milliseconds()
+}
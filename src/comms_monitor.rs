@@ -0,0 +1,46 @@
+use std::time::{Duration, Instant};
+
+// Tracks when a PLC was last heard from, so an outage is reported once when it starts
+// and once when it ends, however many connections come and go in between.
+// Nothing is reported until the first packet, so a PLC that has never connected isn't an outage.
+#[derive(Default)]
+pub struct CommsMonitor {
+    last_packet: Option<Instant>,
+    lost: bool,
+}
+
+impl CommsMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Call for every packet received. Returns how long the outage lasted, if comms had been lost.
+    pub fn packet_received(&mut self) -> Option<Duration> {
+        let silent = self.last_packet.replace(Instant::now()).map(|last| last.elapsed());
+        if self.lost {
+            self.lost = false;
+            silent
+        } else {
+            None
+        }
+    }
+
+    // Returns how long the PLC has been silent, the first time it goes past `timeout`.
+    pub fn check(&mut self, timeout: Duration) -> Option<Duration> {
+        let silent = self.last_packet?.elapsed();
+        if !self.lost && silent >= timeout {
+            self.lost = true;
+            Some(silent)
+        } else {
+            None
+        }
+    }
+
+    // When `check` next needs calling
+    pub fn deadline(&self, timeout: Duration) -> Instant {
+        match self.last_packet {
+            Some(last) if !self.lost => last + timeout,
+            _ => Instant::now() + timeout, // Nothing to do until packets start or resume
+        }
+    }
+}
@@ -1,43 +1,276 @@
-use rust7::client::{S7Client};
+// Reads and writes PLC memory with S7 communication (PUT/GET), for PLCs that can't be
+// reprogrammed to send their events with TSEND. rust7 blocks, so call these through a server's
+// S7Session, which runs them on a blocking thread.
+use std::sync::Arc;
+use rust7::client::S7Client;
+use serde::{Serialize, Deserialize};
+use crate::constants::EVENT_TYPE_PLC;
+use crate::event_data::EventDataPacket;
+use crate::payload_schema::{NamedValue, PlcType, PAYLOAD_SCHEMAS};
+use crate::utils::log_warn;
 
-pub fn connect() -> S7Client {
-    let mut client = S7Client::new();
-    let db_number: u16 = 100; // Must exist into the PLC
-
-    // Connection
-    match client.connect_s71200_1500("192.168.0.100") {
-        Ok(_) => { println!("Connected to PLC") },
-        Err(e) => {
-            eprintln!("Connection failed: {}", e);
-            return;
+// A DB range read on every poll cycle. When its bytes change they're stored as an event
+// with this type and code, the bytes being the event's data.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PollArea {
+    pub db: u16,
+    pub start: u16, // Byte offset in the DB
+    pub size: u16, // Bytes. A multiple of 4 unless a payload schema describes the event.
+    #[serde(default = "default_data_type")]
+    pub data_type: u32,
+    pub plc_packet_code: u32,
+}
+
+fn default_data_type() -> u32 { EVENT_TYPE_PLC }
+
+impl PollArea {
+    // The packet the PLC would have sent with TSEND: type, code, then the area's bytes
+    pub fn frame(&self, bytes: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(8 + bytes.len());
+        frame.extend_from_slice(&self.data_type.to_be_bytes());
+        frame.extend_from_slice(&self.plc_packet_code.to_be_bytes());
+        frame.extend_from_slice(bytes);
+        frame
+    }
+
+    // An area whose events could never be parsed. Without a payload schema the data is DINTs.
+    pub fn check(&self) -> Result<(), String> {
+        if self.size == 0 {
+            return Err(format!("<PollArea> DB{}.{} has a size of 0", self.db, self.start));
         }
+        if !self.size.is_multiple_of(4) && PAYLOAD_SCHEMAS.find(self.data_type, self.plc_packet_code).is_none() {
+            return Err(format!("<PollArea> DB{}.{} is {} bytes, which isn't a whole number of DINTs, and no payload schema describes type {} code {}",
+                self.db, self.start, self.size, self.data_type, self.plc_packet_code));
+        }
+        Ok(())
     }
-    client
 }
 
-pub fn disconnect(client: &mut S7Client) {
-    client.disconnect();
-    println!("Disconnected from PLC");
+// When an event with this data_type and plc_packet_code is stored, the areas are read and
+// stored linked to it. If plc_packet_code is missing every code of the data_type matches.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SnapshotTrigger {
+    pub data_type: u32,
+    #[serde(default)]
+    pub plc_packet_code: Option<u32>,
+    #[serde(rename = "SnapshotArea", default)]
+    pub areas: Vec<SnapshotArea>,
 }
 
-pub fn read_data(client: &mut S7Client, db_number: u16, start_address: u16, size: usize) {
-    match client.read_area(rust7::areas::DataBlock(db_number), start_address, size) {
-        Ok(data) => {
-            println!("Data read from PLC: {:?}", data);
-        },
-        Err(e) => {
-            eprintln!("Read failed: {}", e);
+impl SnapshotTrigger {
+    pub fn matches(&self, packet: &EventDataPacket) -> bool {
+        self.data_type == packet.data_type && self.plc_packet_code.is_none_or(|code| code == packet.plc_packet_code)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SnapshotArea {
+    pub db: u16,
+    pub start: u16, // Byte offset in the DB
+    pub size: u16, // Bytes
+    #[serde(default)]
+    pub schema: String, // Name of a schema in schemas.xml. Empty stores the bytes only.
+}
+
+impl SnapshotArea {
+    // Field offsets in the schema are from `start`
+    pub fn decode(&self, bytes: &[u8]) -> Vec<NamedValue> {
+        if self.schema.is_empty() {
+            return vec![];
+        }
+        match PAYLOAD_SCHEMAS.find_by_name(&self.schema) {
+            Some(schema) => schema.decode(bytes),
+            None => {
+                log_warn(&format!("Snapshot of DB{}.{} uses schema '{}', which isn't in the schema file.", self.db, self.start, self.schema));
+                vec![]
+            }
         }
     }
 }
 
-pub fn write_data(client: &mut S7Client, db_number: u16, start_address: u16, data: &[u8]) {
-    match client.write_area(rust7::areas::DataBlock(db_number), start_address, data) {
-        Ok(_) => {
-            println!("Data written to PLC successfully");
-        },
-        Err(e) => {
-            eprintln!("Write failed: {}", e);
+// A value the operator can write to the PLC from the Connections tab, e.g. to acknowledge alarms
+// or reset a shift counter.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct WriteAction {
+    pub name: String, // Shown on the button, and how the audit records refer to it
+    pub db: u16,
+    pub start: u16, // Byte offset in the DB
+    #[serde(default)]
+    pub bit: u8, // BOOL only
+    #[serde(rename = "type")]
+    pub plc_type: PlcType,
+    #[serde(default)]
+    pub value: String, // What's written, or the starting value when the operator enters it
+    #[serde(default)]
+    pub operator_value: bool, // The operator enters the value, e.g. a downtime reason code
+}
+
+impl WriteAction {
+    // Where it writes, in TIA Portal's notation
+    pub fn address(&self) -> String {
+        match self.plc_type {
+            PlcType::Bool => format!("DB{}.DBX{}.{}", self.db, self.start, self.bit),
+            PlcType::Byte => format!("DB{}.DBB{}", self.db, self.start),
+            PlcType::Int | PlcType::Uint => format!("DB{}.DBW{}", self.db, self.start),
+            _ => format!("DB{}.DBD{}", self.db, self.start),
         }
     }
-}
\ No newline at end of file
+
+    // What gets written: the operator's value if they enter it, otherwise the one in config.xml
+    pub fn value_to_write<'a>(&'a self, entered: &'a str) -> &'a str {
+        if self.operator_value { entered } else { &self.value }
+    }
+
+    // The bytes to write at `start`. A BOOL is the byte already there (`current`) with one bit changed.
+    pub fn encode(&self, value: &str, current: u8) -> Result<Vec<u8>, String> {
+        let value = value.trim();
+        let invalid = || format!("'{}' isn't a valid {:?} for {}", value, self.plc_type, self.name);
+        let bytes = match self.plc_type {
+            PlcType::Bool => {
+                if self.bit > 7 {
+                    return Err(format!("Bit {} of {} doesn't exist, it must be 0 to 7", self.bit, self.name));
+                }
+                let set = match value.to_ascii_lowercase().as_str() {
+                    "1" | "true" => true,
+                    "0" | "false" => false,
+                    _ => return Err(format!("'{}' isn't a valid Bool for {}, use true or false", value, self.name)),
+                };
+                let mask = 1 << self.bit;
+                vec![if set { current | mask } else { current & !mask }]
+            }
+            PlcType::Byte => vec![value.parse::<u8>().map_err(|_| invalid())?],
+            PlcType::Int => value.parse::<i16>().map_err(|_| invalid())?.to_be_bytes().to_vec(),
+            PlcType::Uint => value.parse::<u16>().map_err(|_| invalid())?.to_be_bytes().to_vec(),
+            PlcType::Dint => value.parse::<i32>().map_err(|_| invalid())?.to_be_bytes().to_vec(),
+            PlcType::Udint => value.parse::<u32>().map_err(|_| invalid())?.to_be_bytes().to_vec(),
+            PlcType::Real => value.parse::<f32>().map_err(|_| invalid())?.to_be_bytes().to_vec(),
+            PlcType::String => return Err(format!("{} is a String, which can't be written", self.name)),
+        };
+        Ok(bytes)
+    }
+}
+
+// The one S7 connection a server has to its PLC, which only has a few to go round. Polling,
+// snapshots, the downtime DB, clock checks and writes take turns on it, in the order they ask.
+// It stays open between requests, and is opened again after a request fails.
+#[derive(Clone, Default)]
+pub struct S7Session {
+    client: Arc<tokio::sync::Mutex<Option<SessionClient>>>,
+}
+
+struct SessionClient {
+    target: (String, u16, u16), // IP address, rack and slot it's connected to
+    client: S7Client,
+}
+
+impl S7Session {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Runs `f` with the client on a blocking thread, once every earlier request has finished.
+    pub async fn with_client<T, F>(&self, ip: String, rack: u16, slot: u16, f: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut S7Client) -> Result<T, String> + Send + 'static,
+    {
+        let mut session = self.client.clone().lock_owned().await;
+        tokio::task::spawn_blocking(move || {
+            let target = (ip, rack, slot);
+            if session.as_ref().is_some_and(|s| s.target != target) {
+                close(session.take());
+            }
+            if session.is_none() {
+                let client = connect(&target.0, target.1, target.2)?;
+                *session = Some(SessionClient { target, client });
+            }
+            let result = f(&mut session.as_mut().expect("connected above").client);
+            if result.is_err() {
+                close(session.take()); // It may be the connection that failed
+            }
+            result
+        }).await.unwrap_or_else(|e| Err(e.to_string()))
+    }
+
+    // Closes the connection, after any request that's already running
+    pub async fn disconnect(&self) {
+        let mut session = self.client.clone().lock_owned().await;
+        let _ = tokio::task::spawn_blocking(move || close(session.take())).await;
+    }
+}
+
+fn close(session: Option<SessionClient>) {
+    if let Some(mut session) = session {
+        disconnect(&mut session.client);
+    }
+}
+
+fn connect(ip: &str, rack: u16, slot: u16) -> Result<S7Client, String> {
+    let mut client = S7Client::new();
+    client.connect_rack_slot(ip, rack, slot).map_err(|e| e.to_string())?;
+    Ok(client)
+}
+
+fn disconnect(client: &mut S7Client) {
+    client.disconnect();
+}
+
+pub fn read_data(client: &mut S7Client, db_number: u16, start_address: u16, size: usize) -> Result<Vec<u8>, String> {
+    let mut buffer = vec![0u8; size];
+    client.read_db(db_number, start_address, &mut buffer).map_err(|e| e.to_string())?;
+    Ok(buffer)
+}
+
+pub fn write_data(client: &mut S7Client, db_number: u16, start_address: u16, data: &[u8]) -> Result<(), String> {
+    client.write_db(db_number, start_address, data).map_err(|e| e.to_string())
+}
+
+// The PLC's clock, which S7 CPUs keep in local time
+pub fn read_clock(client: &mut S7Client) -> Result<chrono::NaiveDateTime, String> {
+    client.get_plc_date_time().map_err(|e| e.to_string())
+}
+
+pub fn write_clock(client: &mut S7Client, time: chrono::NaiveDateTime) -> Result<(), String> {
+    client.set_plc_date_time(&time).map_err(|e| e.to_string())
+}
+
+// Writes an action's value. A BOOL's byte is read first, so the other 7 bits are written back as they were.
+// Returns the bytes written.
+pub fn write_action(client: &mut S7Client, action: &WriteAction, value: &str) -> Result<Vec<u8>, String> {
+    let current = match action.plc_type {
+        PlcType::Bool => read_data(client, action.db, action.start, 1)?[0],
+        _ => 0,
+    };
+    let bytes = action.encode(value, current)?;
+    write_data(client, action.db, action.start, &bytes)?;
+    Ok(bytes)
+}
+
+pub fn read_area(client: &mut S7Client, area: &PollArea) -> Result<Vec<u8>, String> {
+    read_data(client, area.db, area.start, area.size as usize)
+}
+
+// Remembers what each area held at the last read, to tell which ones changed.
+// Kept across reconnects, so a change made while the connection was down is still stored.
+// The first read is a change too, so the starting values are on record, including any
+// change made while the monitor wasn't running.
+pub struct ChangeDetector {
+    last: Vec<Option<Vec<u8>>>,
+}
+
+impl ChangeDetector {
+    pub fn new(areas: usize) -> Self {
+        ChangeDetector { last: vec![None; areas] }
+    }
+
+    // True if `bytes` differ from the area's last read, or it's the area's first read.
+    pub fn changed(&mut self, area: usize, bytes: &[u8]) -> bool {
+        let previous = self.last[area].replace(bytes.to_vec());
+        previous.is_none_or(|p| p != bytes)
+    }
+
+    // For a change that couldn't be stored, so the next read counts as a change and it's tried again
+    pub fn forget(&mut self, area: usize) {
+        self.last[area] = None;
+    }
+}
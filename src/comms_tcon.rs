@@ -3,53 +3,186 @@ use std::collections::HashMap;
 use std::sync::{Arc, mpsc};
 use chrono::Local;
 use serde::{Serialize, Deserialize};
-use tokio::sync::{/*mpsc,*/ Notify};
+use tokio::sync::{/*mpsc,*/ watch, Notify};
 use tokio::time::{Duration};
-use tokio::net::{TcpListener};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use crate::constants::DEBUG;
+use rusqlite::Connection;
+use crate::clock_drift::{self, DriftMonitor};
+use crate::comms_monitor::CommsMonitor;
+use crate::comms_s7::{self, ChangeDetector, PollArea, S7Session, SnapshotArea, SnapshotTrigger, WriteAction};
+use crate::constants::*;
+use crate::framing::*;
+use crate::iso_on_tcp::*;
+use crate::payload_schema::{NamedValue, PayloadSchema, Value};
+use crate::plc_time::TimestampFormat;
+use crate::response::*;
+use crate::sequence::*;
+use crate::server_id::ServerId;
 use crate::sql::*;
 use crate::event_data::*;
 use crate::utils::*;
-use crate::xmlhandling::load_config;
-use once_cell::sync::Lazy;
+use crate::config_store::{config_path, SERVER_CONFIG};
 
 #[derive(Debug, Clone)]
 pub enum ServerCommand {
-    Start(usize),
-    Stop(usize),
+    Start(ServerId),
+    Stop(ServerId),
     StopAll,
-    AddServer(ServerEntry),
-    RemoveServer(usize),
+    AddServer(Box<ServerEntry>),
+    RemoveServer(ServerId),
+    ReloadConfig(ServerConfig), // config.xml was edited while running
+    SetPlcClock(ServerId, std::net::IpAddr), // Write the PC's time to the PLC at this address, once the operator has confirmed
+    WriteToPlc(ServerId, std::net::IpAddr, String, String), // Run the named write action with this value, once the operator has confirmed
 }
 
 #[derive(Clone, Copy, Debug)]
 pub struct ServerStatusInfo {
-    pub idx: usize,
-    pub server_id: [u8; 32], // Unique identifier for the server
+    pub server_id: ServerId, // Same as the ServerEntry's id
     pub new_data: bool,
     pub is_running: bool,
     pub is_connected: bool,
     pub is_alive: bool,
     pub last_packet_time: u64,
     pub peer_ip: [u8; 16], // Can hold IPv4 or IPv6, convert to/from string as needed
+    pub plc_config: Option<PlcRuntimeConfig>, // Last configuration packet from the PLC
+    pub duplicate_packets: u64, // Packets dropped because their sequence number was already seen
+    pub lost_packets: u64, // Packets missing from the sequence
+    pub clock_skew_ms: Option<i64>, // Receive time minus PLC time, from the last timestamped keepalive
+    pub clock_drift_ms: Option<i64>, // PLC time minus PC time, from the last clock check over S7
+    pub bind_failed: bool, // Couldn't listen on the configured address, still retrying
+    pub listening_port: u16, // Port actually bound, 0 until bound. Differs from the config's port when that is 0.
+    pub last_error: [u8; 128], // Why, for the UI
+    pub stats: TrafficStats,
+    pub peers: [PeerStatus; MAX_PEERS], // One per connection, e.g. both CPUs of a redundant pair
 }
 impl ServerStatusInfo {
-    pub fn set_server_id(&mut self, id: &str) {
-        let mut id_bytes = [0u8; 32];
-        let bytes = id.as_bytes();
-        let len = bytes.len().min(32);
-        id_bytes[..len].copy_from_slice(&bytes[..len]);
-        self.server_id = id_bytes;
+    pub fn new(server_id: ServerId) -> Self {
+        ServerStatusInfo {
+            server_id,
+            new_data: false,
+            is_running: false,
+            is_connected: false,
+            is_alive: false,
+            last_packet_time: 0,
+            peer_ip: [0; 16],
+            plc_config: None,
+            duplicate_packets: 0,
+            lost_packets: 0,
+            clock_skew_ms: None,
+            clock_drift_ms: None,
+            bind_failed: false,
+            listening_port: 0,
+            last_error: [0; 128],
+            stats: TrafficStats::default(),
+            peers: [PeerStatus::default(); MAX_PEERS],
+        }
     }
-    
-    pub fn get_server_id(&self) -> &[u8; 32] {
+
+    pub fn get_server_id(&self) -> &ServerId {
         &self.server_id
     }
     
-    pub fn matches_server_id(&self, id: &[u8; 32]) -> bool {
+    pub fn matches_server_id(&self, id: &ServerId) -> bool {
         self.get_server_id() == id
     }
+
+    pub fn set_last_error(&mut self, error: &str) {
+        self.last_error = string_to_fixed_array(error);
+    }
+
+    pub fn get_last_error(&self) -> String {
+        fixed_array_to_string(&self.last_error)
+    }
+
+    pub fn set_peer_ip(&mut self, ip: std::net::IpAddr) {
+        self.peer_ip = ip_to_bytes(ip);
+    }
+
+    pub fn peer_ip_addr(&self) -> std::net::IpAddr {
+        bytes_to_ip(&self.peer_ip)
+    }
+}
+
+// One connection to a server. Slots are reused, and keep the last peer's details until then.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PeerStatus {
+    pub ip: [u8; 16], // Same layout as ServerStatusInfo::peer_ip, all zeros for an unused slot
+    pub connected: bool,
+    pub alive: bool,
+    pub connected_since: u64,
+    pub last_packet_time: u64,
+    pub packets: u64,
+}
+
+impl PeerStatus {
+    pub fn is_used(&self) -> bool {
+        self.ip != [0; 16]
+    }
+
+    pub fn ip_addr(&self) -> std::net::IpAddr {
+        bytes_to_ip(&self.ip)
+    }
+}
+
+// Counters for one server, kept across connections for as long as the server runs
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TrafficStats {
+    pub packets: u64,
+    pub bytes: u64,
+    pub parse_errors: u64, // Including framing errors
+    pub db_errors: u64,
+    pub stored: u64, // Events written to the database, so the Events tab knows when to re-read
+    pub snapshots: u64, // Snapshot areas written to the database, likewise
+    pub keepalives: u64,
+    pub connections: u64,
+    pub open_connections: u32,
+    pub connected_since: u64, // Millisecond timestamp, 0 when not connected
+    window_start: u64, // Start of the current one minute window, for the packet rate
+    window_packets: u64,
+    last_window_packets: u64,
+}
+
+impl TrafficStats {
+    pub fn packet(&mut self, size: usize) {
+        let now = Local::now().timestamp_millis() as u64;
+        if now.saturating_sub(self.window_start) >= 60_000 {
+            self.last_window_packets = self.packets_per_min(now);
+            self.window_start = now;
+            self.window_packets = 0;
+        }
+        self.window_packets += 1;
+        self.packets += 1;
+        self.bytes += size as u64;
+    }
+
+    // Packets in the last full minute
+    pub fn packets_per_min(&self, now: u64) -> u64 {
+        match now.saturating_sub(self.window_start) {
+            age if age < 60_000 => self.last_window_packets,
+            age if age < 120_000 => self.window_packets,
+            _ => 0, // Nothing for over a minute
+        }
+    }
+
+    pub fn connected(&mut self) {
+        self.connections += 1;
+        if self.open_connections == 0 {
+            self.connected_since = Local::now().timestamp_millis() as u64;
+        }
+        self.open_connections += 1;
+    }
+
+    pub fn disconnected(&mut self) {
+        self.open_connections = self.open_connections.saturating_sub(1);
+        if self.open_connections == 0 {
+            self.connected_since = 0;
+        }
+    }
+
+    pub fn reconnects(&self) -> u64 {
+        self.connections.saturating_sub(1)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -60,68 +193,68 @@ pub struct ServerStatus {
 impl ServerStatus {
     pub fn new() -> Self {
         ServerStatus {
-            server: vec![ServerStatusInfo {
-                idx: 0,
-                server_id: generate_server_id(),
-                new_data: false,
-                is_running: false,
-                is_connected: false,
-                is_alive: false,
-                last_packet_time: 0,
-                peer_ip: [0; 16],
-            }],
+            server: Vec::new(),
         }
     }
 
-    pub fn set_ipv4(&mut self, i: usize, ip: std::net::Ipv4Addr) {
+    pub fn get(&self, id: &ServerId) -> Option<&ServerStatusInfo> {
+        self.server.iter().find(|s| s.matches_server_id(id))
+    }
+
+    pub fn get_mut(&mut self, id: &ServerId) -> Option<&mut ServerStatusInfo> {
+        self.server.iter_mut().find(|s| s.matches_server_id(id))
+    }
+
+    pub fn set_ipv4(&mut self, id: &ServerId, ip: std::net::Ipv4Addr) {
         // Store IPv4 in the first 4 bytes, rest zeros
-        let octets = ip.octets();
-        self.server[i].peer_ip = [0; 16];
-        self.server[i].peer_ip[0..4].copy_from_slice(&octets);
-        log(&format!("Set IPv4 for server {}: {:?}", i, self.server[i].peer_ip));
+        if let Some(status) = self.get_mut(id) {
+            status.peer_ip = ip_to_bytes(ip.into());
+            log(&format!("Set IPv4 for server {}: {:?}", id, status.peer_ip));
+        }
     }
     
-    pub fn set_ipv6(&mut self, i: usize, ip: std::net::Ipv6Addr) {
-        self.server[i].peer_ip = ip.octets();
+    pub fn set_ipv6(&mut self, id: &ServerId, ip: std::net::Ipv6Addr) {
+        if let Some(status) = self.get_mut(id) {
+            status.peer_ip = ip.octets();
+        }
     }
 
-    pub fn get_ip_string(&self, i: usize) -> String {
-        // Check bounds first
-        if i >= self.server.len() {
-            if DEBUG {log(&format!("Warning: Requested IP string for server {} but only {} servers exist", i, self.server.len()))};
+    pub fn get_ip_string(&self, id: &ServerId) -> String {
+        let Some(status) = self.get(id) else {
+            if debug_logging() {log(&format!("Warning: Requested IP string for server {} but it has no status", id))};
             return "x.x.x.x".to_string();
-        }
+        };
 
         // Check if it's IPv4 (first 4 bytes non-zero, rest zero)
-        if DEBUG {log(&format!("Getting IP string for server {}: {:?}", i, self.server[i].peer_ip));}
-        if self.server[i].peer_ip[4..].iter().all(|&x| x == 0) {
+        if debug_logging() {log(&format!("Getting IP string for server {}: {:?}", id, status.peer_ip));}
+        if status.peer_ip[4..].iter().all(|&x| x == 0) {
             let ipv4 = std::net::Ipv4Addr::new(
-                self.server[i].peer_ip[0], 
-                self.server[i].peer_ip[1], 
-                self.server[i].peer_ip[2], 
-                self.server[i].peer_ip[3]
+                status.peer_ip[0], 
+                status.peer_ip[1], 
+                status.peer_ip[2], 
+                status.peer_ip[3]
             );
             if ipv4.is_unspecified() {
-                if DEBUG {log(&format!("Server {} has unspecified IPv4 address, returning x.x.x.x", i))};
+                if debug_logging() {log(&format!("Server {} has unspecified IPv4 address, returning x.x.x.x", id))};
                 "x.x.x.x".to_string()
             } else {
-                if DEBUG {log(&format!("Server {} IPv4 address: {}", i, ipv4))};
+                if debug_logging() {log(&format!("Server {} IPv4 address: {}", id, ipv4))};
                 ipv4.to_string()
             }
         } else {
             // IPv6
-            let ipv6 = std::net::Ipv6Addr::from(self.server[i].peer_ip);
+            let ipv6 = std::net::Ipv6Addr::from(status.peer_ip);
             ipv6.to_string()
         }
     }
 
-    pub fn set_ip_from_string(&mut self, i: usize, ip_str: &str) -> Result<(), std::net::AddrParseError> {
-        log(&format!("Setting IP for server {}: {}", i, ip_str));
+    pub fn set_ip_from_string(&mut self, id: &ServerId, ip_str: &str) -> Result<(), std::net::AddrParseError> {
+        log(&format!("Setting IP for server {}: {}", id, ip_str));
         if let Ok(ipv4) = ip_str.parse::<std::net::Ipv4Addr>() {
-            self.set_ipv4(i,ipv4);
+            self.set_ipv4(id, ipv4);
             Ok(())
         } else if let Ok(ipv6) = ip_str.parse::<std::net::Ipv6Addr>() {
-            self.set_ipv6(i, ipv6);
+            self.set_ipv6(id, ipv6);
             Ok(())
         } else {
             Err("Invalid IP address".parse::<std::net::Ipv4Addr>().unwrap_err())
@@ -129,56 +262,211 @@ impl ServerStatus {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+// Which side opens the TCP connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionMode {
+    #[default]
+    Listen, // The PLC connects to us (TCON active)
+    Connect, // We connect to the PLC (TCON passive)
+}
+
+// How the PLC's packets reach us
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transport {
+    #[default]
+    Tcp, // TCON + TSEND/TRCV
+    Udp, // TUSEND/TURCV, one datagram per packet
+    IsoOnTcp, // TCON type 0x12, RFC 1006 with TSAPs
+    S7Poll, // We read the PLC's poll areas with PUT/GET, nothing to program in the PLC
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerConfig {
     #[serde(rename = "Server")]
     pub server: Vec<ServerEntry>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ServerEntry {
-    #[serde(
-        serialize_with = "crate::xmlhandling::serialize_bytes_as_base64",
-        deserialize_with = "crate::xmlhandling::deserialize_bytes_from_base64"
-    )]
-    pub id: [u8; 32], // Unique identifier for each server
+    #[serde(default = "ServerId::new")]
+    pub id: ServerId, // Unique identifier for each server, never reused
     pub name: String,
     pub ip_address: String,
     pub port: u16,
     pub autostart: bool,
+    #[serde(default)]
+    pub framing: FramingMode,
+    #[serde(default)]
+    pub record_length: usize, // Only used with FramingMode::FixedRecord
+    #[serde(default)]
+    pub sequence_numbers: bool, // PLC puts a rolling sequence counter after the packet code
+    #[serde(default)]
+    pub plc_timestamps: TimestampFormat, // PLC puts a DTL or LDT after the sequence counter
+    #[serde(default)]
+    pub response_format: ResponseFormat,
+    #[serde(default)]
+    pub connection_mode: ConnectionMode, // With Connect, ip_address and port are the PLC's
+    #[serde(default)]
+    pub transport: Transport, // With Udp, framing and connection_mode aren't used
+    #[serde(default)]
+    pub local_tsap: String, // IsoOnTcp only, hex e.g. "E0.01". Empty accepts any
+    #[serde(default)]
+    pub remote_tsap: String, // IsoOnTcp only, the PLC's TSAP. Empty accepts any
+    #[serde(default = "default_bind_retry_min_secs")]
+    pub bind_retry_min_secs: u64, // First delay after a failed bind, doubled on every failure
+    #[serde(default = "default_bind_retry_max_secs")]
+    pub bind_retry_max_secs: u64,
+    #[serde(default = "default_keepalive_timeout_secs")]
+    pub keepalive_timeout_secs: u64, // Silence before the PLC counts as not alive and comms lost is stored
+    #[serde(rename = "AllowedPeer", default)]
+    pub allowed_peers: Vec<String>, // IP addresses that may send packets. Empty allows any
+    #[serde(default)]
+    pub read_downtime_db: bool, // Read the DB named in configuration packets over S7, using rack and slot
+    #[serde(default)]
+    pub downtime_schema: String, // Payload schema describing the downtime DB. Empty reads 5 DInts
+    #[serde(default)]
+    pub rack: u16, // For every S7 connection to the PLC
+    #[serde(default = "default_slot")]
+    pub slot: u16,
+    #[serde(default = "default_poll_interval_ms")]
+    pub poll_interval_ms: u64, // S7Poll only, time between reads of every poll area
+    #[serde(rename = "PollArea", default)]
+    pub poll_areas: Vec<PollArea>, // S7Poll only
+    #[serde(rename = "SnapshotTrigger", default)]
+    pub snapshot_triggers: Vec<SnapshotTrigger>, // DB ranges read over S7 when a matching event is stored
+    #[serde(default)]
+    pub clock_check_secs: u64, // How often the PLC's clock is read over S7 while it's connected, 0 for never
+    #[serde(default = "default_max_clock_drift_ms")]
+    pub max_clock_drift_ms: u64, // Drift that stores a clock drift warning
+    #[serde(default)]
+    pub allow_set_clock: bool, // Shows Set PLC Clock on the Connections tab
+    #[serde(rename = "WriteAction", default)]
+    pub write_actions: Vec<WriteAction>, // Values the operator can write to the PLC over S7
+}
+
+fn default_bind_retry_min_secs() -> u64 { BIND_RETRY_MIN_SECS }
+fn default_bind_retry_max_secs() -> u64 { BIND_RETRY_MAX_SECS }
+fn default_keepalive_timeout_secs() -> u64 { KEEPALIVE_TIMEOUT_SECS }
+fn default_slot() -> u16 { S7_DEFAULT_SLOT }
+fn default_poll_interval_ms() -> u64 { POLL_INTERVAL_MS }
+fn default_max_clock_drift_ms() -> u64 { DEFAULT_MAX_CLOCK_DRIFT_MS }
+
+impl ServerEntry {
+    pub fn packet_layout(&self) -> PacketLayout {
+        PacketLayout {
+            sequence: self.sequence_numbers,
+            timestamp: self.plc_timestamps,
+        }
+    }
+
+    pub fn allows_peer(&self, ip: std::net::IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.allowed_peers.is_empty()
+            || self.allowed_peers.iter().any(|p| p.trim().parse::<std::net::IpAddr>().is_ok_and(|allowed| allowed == ip))
+    }
+
+    pub fn keepalive_timeout(&self) -> Duration {
+        Duration::from_secs(self.keepalive_timeout_secs.max(1))
+    }
+
+    pub fn tsaps(&self) -> Result<(Vec<u8>, Vec<u8>), &'static str> {
+        Ok((parse_tsap(&self.local_tsap)?, parse_tsap(&self.remote_tsap)?))
+    }
 }
 
-// Helper function to generate unique server IDs
-pub fn generate_server_id() -> [u8;32] {
-    use std::time::{SystemTime, UNIX_EPOCH};
-    let timestamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_nanos();
-    let id_string = format!("{}", timestamp);
-    string_to_fixed_array(&id_string)
+impl Default for ServerEntry {
+    fn default() -> Self {
+        ServerEntry {
+            id: ServerId::new(),
+            name: "New Server".to_string(),
+            ip_address: "0.0.0.0".to_string(),
+            port: 2000,
+            autostart: false,
+            framing: FramingMode::Raw,
+            record_length: 0,
+            sequence_numbers: false,
+            plc_timestamps: TimestampFormat::None,
+            response_format: ResponseFormat::Legacy,
+            connection_mode: ConnectionMode::Listen,
+            transport: Transport::Tcp,
+            local_tsap: String::new(),
+            remote_tsap: String::new(),
+            bind_retry_min_secs: BIND_RETRY_MIN_SECS,
+            bind_retry_max_secs: BIND_RETRY_MAX_SECS,
+            keepalive_timeout_secs: KEEPALIVE_TIMEOUT_SECS,
+            allowed_peers: Vec::new(),
+            read_downtime_db: false,
+            downtime_schema: String::new(),
+            rack: 0,
+            slot: S7_DEFAULT_SLOT,
+            poll_interval_ms: POLL_INTERVAL_MS,
+            poll_areas: Vec::new(),
+            snapshot_triggers: Vec::new(),
+            clock_check_secs: 0,
+            max_clock_drift_ms: DEFAULT_MAX_CLOCK_DRIFT_MS,
+            allow_set_clock: false,
+            write_actions: Vec::new(),
+        }
+    }
 }
 
-pub static mut SERVER_CONFIG: Lazy<ServerConfig> = Lazy::new(|| {
-    load_config("config.xml").unwrap_or_else(|_| ServerConfig {
-        server: vec![
-            ServerEntry {
-                id: generate_server_id(),
-                name: "Default Server".to_string(),
-                ip_address: "0.0.0.0".to_string(),
-                port: 2000,
-                autostart: false,
-            }
-        ]
-    })
-});
+// IPv4 goes in the first 4 bytes, the rest zeros
+pub fn ip_to_bytes(ip: std::net::IpAddr) -> [u8; 16] {
+    match ip.to_canonical() {
+        std::net::IpAddr::V4(ipv4) => {
+            let mut bytes = [0u8; 16];
+            bytes[0..4].copy_from_slice(&ipv4.octets());
+            bytes
+        }
+        std::net::IpAddr::V6(ipv6) => ipv6.octets(),
+    }
+}
+
+pub fn bytes_to_ip(bytes: &[u8; 16]) -> std::net::IpAddr {
+    if bytes[4..].iter().all(|&b| b == 0) {
+        std::net::Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]).into()
+    } else {
+        std::net::Ipv6Addr::from(*bytes).into()
+    }
+}
+
+// Tells a server's tasks to stop, either because that server was stopped or the app is closing.
+#[derive(Clone)]
+pub struct ShutdownSignal {
+    app: Arc<Notify>, // Shared by every server
+    server: watch::Receiver<bool>, // Only this server
+}
+
+impl ShutdownSignal {
+    pub fn new(app: Arc<Notify>, server: watch::Receiver<bool>) -> Self {
+        ShutdownSignal { app, server }
+    }
+
+    // Completes once this server has to stop. Dropping the server's sender counts as a stop.
+    pub async fn recv(&mut self) {
+        tokio::select! {
+            _ = self.app.notified() => {}
+            _ = self.server.wait_for(|stop| *stop) => {}
+        }
+    }
+}
+
+// A running server task and the switch that stops it, and only it.
+pub struct ServerHandle {
+    pub task: JoinHandle<()>,
+    pub stop: watch::Sender<bool>,
+    pub s7: S7Session, // For clock setting and writes from the Connections tab
+}
 
 //#[derive(Copy, Debug)]
 pub struct ServerManager {
-    pub handles: HashMap<usize, JoinHandle<()>>,
-    pub shutdown_notify: Arc<Notify>,
+    pub handles: HashMap<ServerId, ServerHandle>,
+    pub shutdown_notify: Arc<Notify>, // App-wide shutdown
     pub tx: std::sync::mpsc::Sender<ServerStatusInfo>,
     pub command_rx: tokio::sync::mpsc::UnboundedReceiver<ServerCommand>,
+    pub config_rx: watch::Receiver<ServerConfig>, // Changes made outside the manager, e.g. from the UI
     pub server_status: ServerStatus,
 }
 
@@ -190,22 +478,18 @@ impl ServerManager {
         let (command_tx, command_rx) = tokio::sync::mpsc::unbounded_channel();
         
         // Initialize with proper number of servers from config
-        let server_count = unsafe { SERVER_CONFIG.server.len() };
+        let servers = SERVER_CONFIG.servers();
         let mut server_status = ServerStatus::new();
-        server_status.server.clear(); // Remove the default entry
+        let conn = connect_to_db().ok();
         
         // Create status entries for each configured server
-        for i in 0..server_count {
-            server_status.server.push(ServerStatusInfo {
-                idx: i,
-                server_id: unsafe{SERVER_CONFIG.server[i].id},
-                new_data: false,
-                is_running: false,
-                is_connected: false,
-                is_alive: false,
-                last_packet_time: 0,
-                peer_ip: [0; 16],
-            });
+        for server in servers.iter() {
+            let mut status = ServerStatusInfo::new(server.id);
+            // Show the last config the PLC sent, even before it reconnects
+            status.plc_config = conn.as_ref()
+                .and_then(|c| latest_plc_config(c, &server.name).ok())
+                .flatten();
+            server_status.server.push(status);
         }
 
         let manager = Self {
@@ -213,6 +497,7 @@ impl ServerManager {
             shutdown_notify,
             tx,
             command_rx,
+            config_rx: SERVER_CONFIG.subscribe(),
             server_status,
         };
         
@@ -220,91 +505,187 @@ impl ServerManager {
     }
 
     pub async fn process_commands(&mut self) {
-        while let Some(command) = self.command_rx.recv().await {
+        loop {
+            let command = tokio::select! {
+                command = self.command_rx.recv() => match command {
+                    Some(command) => command,
+                    None => break, // The UI has gone
+                },
+                Ok(()) = self.config_rx.changed() => {
+                    self.sync_with_config().await;
+                    continue;
+                }
+            };
             match command {
-                ServerCommand::Start(idx) => {
-                    self.start_server(idx).await;
+                ServerCommand::Start(id) => {
+                    self.start_server(id).await;
                 }
-                ServerCommand::Stop(idx) => {
-                    let _ = self.stop_server(idx).await;
+                ServerCommand::Stop(id) => {
+                    let _ = self.stop_server(id).await;
                 }
                 ServerCommand::StopAll => {
                     self.stop_all_servers().await;
                 }
                 ServerCommand::AddServer(server_entry) => {
-                    self.add_server(server_entry).await;
+                    self.add_server(*server_entry).await;
                 }
-                ServerCommand::RemoveServer(idx) => {
-                    let _ = self.remove_server(idx).await;
-                    self.sync_with_config().await;
+                ServerCommand::RemoveServer(id) => {
+                    let _ = self.remove_server(id).await;
+                }
+                ServerCommand::ReloadConfig(new_config) => {
+                    self.reload_config(new_config).await;
+                }
+                ServerCommand::SetPlcClock(id, ip) => {
+                    self.set_plc_clock(id, ip);
+                }
+                ServerCommand::WriteToPlc(id, ip, action, value) => {
+                    self.write_to_plc(id, ip, action, value);
                 }
             }
         }
     }
 
-    pub async fn start_server(&mut self, server_index: usize) {
-        if self.handles.contains_key(&server_index) {
-            log(&format!("Server {} is already running", server_index));
+    pub async fn start_server(&mut self, server_id: ServerId) {
+        let Some(server) = SERVER_CONFIG.server(&server_id) else {
+            log_warn(&format!("Can't start server {}, it isn't in the config", server_id));
+            return;
+        };
+        if self.handles.contains_key(&server_id) {
+            log(&format!("Server {} is already running", server.name));
             return;
         }
+        log(&format!("Starting server {} ({})", server.name, server_id));
 
-        println!("Starting server {}", server_index);
-
-        let shutdown_notify = self.shutdown_notify.clone();
+        let (stop, stop_rx) = watch::channel(false);
+        let shutdown = ShutdownSignal::new(self.shutdown_notify.clone(), stop_rx);
         let tx = self.tx.clone();
         
         // Get initial status for this server
-        let initial_status = self.server_status.server.get(server_index)
-            .cloned()
-            .unwrap_or(ServerStatusInfo {
-                idx: server_index,
-                server_id: unsafe { SERVER_CONFIG.server[server_index].id },
-                new_data: false,
-                is_running: true,
-                is_connected: false,
-                is_alive: false,
-                last_packet_time: 0,
-                peer_ip: [0; 16],
-            });
+        if self.server_status.get(&server_id).is_none() {
+            self.server_status.server.push(ServerStatusInfo::new(server_id));
+        }
+        let status = self.server_status.get_mut(&server_id).expect("status was just added");
+        status.is_running = true;
+        let initial_status = *status;
 
-        let handle = tokio::spawn(async move {
-            if let Err(e) = run_server(shutdown_notify, server_index, tx, initial_status).await {
-                log(&format!("Server {} exited with error: {}", server_index, e));
+        let name = server.name.clone();
+        let s7 = S7Session::new();
+        let server_s7 = s7.clone();
+        let task = tokio::spawn(async move {
+            if let Err(e) = run_server(shutdown, server_id, tx, initial_status, server_s7).await {
+                log_error(&format!("Server {} exited with error: {}", name, e));
             } else {
-                log(&format!("Server {} exited normally", server_index));
+                log(&format!("Server {} exited normally", name));
             }
         });
         
-        self.handles.insert(server_index, handle);
-        self.server_status.server[server_index].is_running = true;
+        self.handles.insert(server_id, ServerHandle { task, stop, s7 });
 
         // Send the updated status to the UI immediately
-        let _ = self.tx.send(self.server_status.server[server_index]);
+        let _ = self.tx.send(initial_status);
         
-        if DEBUG { log(&format!("Started server {}", server_index)); }
+        if debug_logging() { log(&format!("Started server {}", server.name)); }
+    }
+
+    // Reads the PLC's clock, then writes the PC's time to it. The drift it had is stored with the event.
+    pub fn set_plc_clock(&self, server_id: ServerId, ip: std::net::IpAddr) {
+        let Some(server) = SERVER_CONFIG.server(&server_id) else {
+            log_warn(&format!("Can't set the PLC clock for server {}, it isn't in the config", server_id));
+            return;
+        };
+        if !server.allow_set_clock {
+            log_warn(&format!("Can't set the PLC clock for {}, allow_set_clock isn't set in config.xml", server.name));
+            return;
+        }
+        let Some(s7) = self.handles.get(&server_id).map(|h| h.s7.clone()) else {
+            log_warn(&format!("Can't set the PLC clock for {}, the server isn't running", server.name));
+            return;
+        };
+        tokio::spawn(async move {
+            let result = s7.with_client(ip.to_string(), server.rack, server.slot, |client| {
+                let before = Local::now();
+                let plc_time = comms_s7::read_clock(client)?;
+                let now = Local::now();
+                comms_s7::write_clock(client, now.naive_local())?;
+                Ok(clock_drift::drift_ms(plc_time, before, now))
+            }).await;
+
+            match result {
+                Ok(drift) => {
+                    log(&format!("Set the clock of {} ({}) to the PC's time, it was {} ms out.", server.name, ip, drift));
+                    let mut event = EventDataPacket::internal(PACKET_CODE_CLOCK_SET, vec![drift as i32 as u32]);
+                    event.values = vec![NamedValue { name: "drift".to_string(), value: Value::Number(drift as f64), unit: "ms".to_string() }];
+                    match connect_to_db() {
+                        Ok(conn) => { store(&conn, &event, &server.name); }
+                        Err(e) => log_error(&format!("Error opening the database for {}'s clock: {}", server.name, e)),
+                    }
+                }
+                Err(e) => log_warn(&format!("Can't set the clock of {} ({}): {}", server.name, ip, e)),
+            }
+        });
+    }
+
+    // Runs one of the server's write actions. Every attempt is audited, including ones that fail.
+    pub fn write_to_plc(&self, server_id: ServerId, ip: std::net::IpAddr, action_name: String, value: String) {
+        let Some(server) = SERVER_CONFIG.server(&server_id) else {
+            log_warn(&format!("Can't write to the PLC for server {}, it isn't in the config", server_id));
+            return;
+        };
+        let Some(action) = server.write_actions.iter().find(|a| a.name == action_name).cloned() else {
+            log_warn(&format!("{} has no write action called '{}'", server.name, action_name));
+            return;
+        };
+        let value = action.value_to_write(&value).to_string();
+        let Some(s7) = self.handles.get(&server_id).map(|h| h.s7.clone()) else {
+            log_warn(&format!("Can't write {} to {}, the server isn't running", action.name, server.name));
+            return;
+        };
+        tokio::spawn(async move {
+            let result = match action.encode(&value, 0) {
+                Err(e) => Err(e), // Don't connect for a value that can't be written
+                Ok(_) => {
+                    let (write, write_value) = (action.clone(), value.clone());
+                    s7.with_client(ip.to_string(), server.rack, server.slot, move |client| {
+                        comms_s7::write_action(client, &write, &write_value)
+                    }).await
+                }
+            };
+
+            match &result {
+                Ok(_) => log(&format!("{}: wrote {} to {} ({}) on {}", server.name, value, action.address(), action.name, ip)),
+                Err(e) => log_warn(&format!("{}: can't write {} to {} ({}): {}", server.name, value, action.address(), action.name, e)),
+            }
+            let stored = connect_to_db().and_then(|conn| match &result {
+                Ok(bytes) => store_write_audit(&conn, &action, &value, Some(bytes), None, &server.name),
+                Err(e) => store_write_audit(&conn, &action, &value, None, Some(e), &server.name),
+            });
+            if let Err(e) = stored {
+                log_error(&format!("Can't store the audit record for {} on {}: {}", action.name, server.name, e));
+            }
+        });
     }
 
-    pub async fn stop_server(&mut self, server_index: usize) -> Result<(), &'static str> {
-        if let Some(handle) = self.handles.remove(&server_index) {
-            // Notify shutdown
-            self.shutdown_notify.notify_waiters();
+    pub async fn stop_server(&mut self, server_id: ServerId) -> Result<(), &'static str> {
+        if let Some(handle) = self.handles.remove(&server_id) {
+            // Only this server's listener and connections see this
+            let _ = handle.stop.send(true);
             
             // Wait for graceful shutdown or force abort
             tokio::select! {
-                result = handle => {
+                result = handle.task => {
                     match result {
-                        Ok(_) => log(&format!("Server {} stopped gracefully", server_index)),
-                        Err(e) => log(&format!("Server {} join error: {}", server_index, e)),
+                        Ok(_) => log(&format!("Server {} stopped gracefully", server_id)),
+                        Err(e) => log_error(&format!("Server {} join error: {}", server_id, e)),
                     }
                 }
                 _ = tokio::time::sleep(Duration::from_secs(5)) => {
-                    log(&format!("Server {} did not stop gracefully, aborting", server_index));
+                    log_warn(&format!("Server {} did not stop gracefully, aborting", server_id));
                     // Handle is dropped here, which should abort the task
                 }
             }
             
             // Update local status and notify UI
-            if let Some(status) = self.server_status.server.get_mut(server_index) {
+            if let Some(status) = self.server_status.get_mut(&server_id) {
                 status.is_running = false;
                 status.is_connected = false;
                 status.is_alive = false;
@@ -314,30 +695,33 @@ impl ServerManager {
             
             Ok(())
         } else {
-            self.server_status.server[server_index].is_running = false;
+            if let Some(status) = self.server_status.get_mut(&server_id) {
+                status.is_running = false;
+            }
             Err("Server not running")
         }
     }
 
-    pub fn is_running(&self, server_index: usize) -> bool {
-        self.handles.contains_key(&server_index)
+    pub fn is_running(&self, server_id: ServerId) -> bool {
+        self.handles.contains_key(&server_id)
     }
 
     pub async fn stop_all_servers(&mut self) {
-        self.shutdown_notify.notify_waiters();
-        
         let handles: Vec<_> = self.handles.drain().collect();
+        for (_, handle) in handles.iter() {
+            let _ = handle.stop.send(true);
+        }
         
-        for (idx, handle) in handles {
+        for (id, handle) in handles {
             tokio::select! {
-                result = handle => {
+                result = handle.task => {
                     match result {
-                        Ok(_) => log(&format!("Server {} stopped", idx)),
-                        Err(e) => log(&format!("Server {} error: {}", idx, e)),
+                        Ok(_) => log(&format!("Server {} stopped", id)),
+                        Err(e) => log_error(&format!("Server {} error: {}", id, e)),
                     }
                 }
                 _ = tokio::time::sleep(Duration::from_secs(5)) => {
-                    log(&format!("Force stopping server {}", idx));
+                    log_warn(&format!("Force stopping server {}", id));
                 }
             }
         }
@@ -345,21 +729,12 @@ impl ServerManager {
 
     pub async fn add_server(&mut self, server_entry: ServerEntry) {
         // First, add to the global config
-        let new_idx = unsafe {
-            SERVER_CONFIG.server.push(server_entry.clone());
-            SERVER_CONFIG.server.len() - 1
-        };
+        SERVER_CONFIG.update(|config| config.server.push(server_entry.clone()));
 
         // Create a new ServerStatusInfo for this server
         let new_status = ServerStatusInfo {
-            idx: new_idx,
-            server_id: server_entry.id,
             new_data: true, // Mark as new data to notify UI
-            is_running: false,
-            is_connected: false,
-            is_alive: false,
-            last_packet_time: 0,
-            peer_ip: [0; 16],
+            ..ServerStatusInfo::new(server_entry.id)
         };
 
         // Add the status to our local server_status
@@ -370,64 +745,102 @@ impl ServerManager {
 
         // Save the config to disk
         /*
-        if let Err(e) = crate::xmlhandling::save_config("config.xml") {
-            log(&format!("Failed to save config after adding server: {}", e));
+        if let Err(e) = crate::xmlhandling::save_config(&config_path()) {
+            log_error(&format!("Failed to save config after adding server: {}", e));
         } else {
             log(&format!("Added new server: {} at {}:{}", 
                 server_entry.name, server_entry.ip_address, server_entry.port));
         }*/
     }
 
-    pub async fn remove_server(&mut self, server_index: usize) -> Result<(), &'static str> {
+    pub async fn remove_server(&mut self, server_id: ServerId) -> Result<(), &'static str> {
         // First, stop the server if it's running
-        if self.is_running(server_index) {
-            let _ = self.stop_server(server_index).await;
+        if self.is_running(server_id) {
+            let _ = self.stop_server(server_id).await;
         }
 
         // Remove from global config
-        unsafe {
-            if server_index >= SERVER_CONFIG.server.len() {
-                return Err("Server index out of bounds");
-            }
-            SERVER_CONFIG.server.remove(server_index);
-        }
-
-        // Remove from local status and reindex remaining servers
-        if server_index < self.server_status.server.len() {
-            self.server_status.server.remove(server_index);
-            
-            // Update indices for remaining servers
-            for (_i, status) in self.server_status.server.iter_mut().enumerate() {
-                if status.idx > server_index {
-                    status.idx -= 1;
-                    status.new_data = true;
-                    let _ = self.tx.send(*status);
-                }
-            }
+        let removed = SERVER_CONFIG.update(|config| {
+            let count = config.server.len();
+            config.server.retain(|s| s.id != server_id);
+            config.server.len() != count
+        });
+        if !removed {
+            return Err("Server isn't in the config");
         }
 
-        // Update handles HashMap to reflect new indices
-        let mut new_handles = HashMap::new();
-        for (idx, handle) in self.handles.drain() {
-            if idx > server_index {
-                new_handles.insert(idx - 1, handle);
-            } else if idx < server_index {
-                new_handles.insert(idx, handle);
-            }
-            // Skip the removed server_index
-        }
-        self.handles = new_handles;
+        // Other servers keep their ids, so nothing else needs to change
+        self.server_status.server.retain(|s| !s.matches_server_id(&server_id));
 
         // Save the config
-        /*if let Err(e) = crate::xmlhandling::save_config("config.xml") {
-            log(&format!("Failed to save config after removing server: {}", e));
+        /*if let Err(e) = crate::xmlhandling::save_config(&config_path()) {
+            log_error(&format!("Failed to save config after removing server: {}", e));
         } else {
-            log(&format!("Removed server at index {}", server_index));
+            log(&format!("Removed server {}", server_id));
         }*/
 
         Ok(())
     }
 
+    // Applies a config.xml that was edited while running. Servers that didn't change are left
+    // alone, so their PLCs stay connected.
+    pub async fn reload_config(&mut self, new_config: ServerConfig) {
+        let old_servers = SERVER_CONFIG.servers();
+        let find = |servers: &[ServerEntry], id: ServerId| servers.iter().find(|s| s.id == id).cloned();
+
+        let removed: Vec<ServerEntry> = old_servers.iter()
+            .filter(|old| find(&new_config.server, old.id).is_none())
+            .cloned()
+            .collect();
+        let added: Vec<ServerEntry> = new_config.server.iter()
+            .filter(|new| find(&old_servers, new.id).is_none())
+            .cloned()
+            .collect();
+        let changed: Vec<ServerEntry> = new_config.server.iter()
+            .filter(|new| find(&old_servers, new.id).is_some_and(|old| old != **new))
+            .cloned()
+            .collect();
+        if removed.is_empty() && added.is_empty() && changed.is_empty() {
+            if debug_logging() { log("config.xml reloaded, no changes"); }
+            return;
+        }
+
+        // Stop anything that's going away or needs restarting with its new settings
+        for server in removed.iter() {
+            log(&format!("Reload: removing server {}", server.name));
+            let _ = self.stop_server(server.id).await;
+        }
+        let mut restart = Vec::new();
+        for server in changed.iter() {
+            if self.is_running(server.id) {
+                log(&format!("Reload: restarting server {} with its new settings", server.name));
+                let _ = self.stop_server(server.id).await;
+                restart.push(server.id);
+            } else {
+                log(&format!("Reload: updated server {}", server.name));
+            }
+        }
+
+        SERVER_CONFIG.update(|config| *config = new_config);
+        self.sync_with_config().await;
+        if !added.is_empty() {
+            // A <Server> added without an <id> was given one, write it back so it keeps it
+            if let Err(e) = crate::xmlhandling::save_config(&config_path()) {
+                log_error(&format!("Failed to save config after reload: {}", e));
+            }
+        }
+
+        for id in restart {
+            self.start_server(id).await;
+        }
+        for server in added.iter() {
+            log(&format!("Reload: added server {}", server.name));
+            if server.autostart {
+                self.start_server(server.id).await;
+            }
+        }
+    }
+
     // Helper method to get current server count
     pub fn server_count(&self) -> usize {
         self.server_status.server.len()
@@ -435,30 +848,25 @@ impl ServerManager {
 
     // Method to refresh server status from config (useful for synchronization)
     pub async fn sync_with_config(&mut self) {
-        let config_count = unsafe { SERVER_CONFIG.server.len() };
-        let status_count = self.server_status.server.len();
+        let servers = SERVER_CONFIG.servers();
+        let in_sync = servers.len() == self.server_status.server.len()
+            && servers.iter().all(|server| self.server_status.get(&server.id).is_some());
 
-        if config_count != status_count {
+        if !in_sync {
             log(&format!("Config/Status mismatch: config has {}, status has {}. Syncing...", 
-                config_count, status_count));
+                servers.len(), self.server_status.server.len()));
 
-            // Clear and rebuild status from config
-            self.server_status.server.clear();
-            
-            for i in 0..config_count {
-                let new_status = ServerStatusInfo {
-                    idx: i,
-                    server_id: unsafe { SERVER_CONFIG.server[i].id },
+            // Keep the status of servers that are still there, in config order
+            let mut statuses = Vec::with_capacity(servers.len());
+            for server in servers.iter() {
+                let status = ServerStatusInfo {
                     new_data: true,
-                    is_running: false,
-                    is_connected: false,
-                    is_alive: false,
-                    last_packet_time: 0,
-                    peer_ip: [0; 16],
+                    ..self.server_status.get(&server.id).copied().unwrap_or_else(|| ServerStatusInfo::new(server.id))
                 };
-                self.server_status.server.push(new_status);
-                let _ = self.tx.send(new_status);
+                statuses.push(status);
+                let _ = self.tx.send(status);
             }
+            self.server_status.server = statuses;
         }
     }
 }
@@ -475,43 +883,216 @@ impl ServerManager {
 }*/
 
 pub async fn run_server(
-    shutdown_notify: Arc<Notify>, 
-    server_number: usize,
+    shutdown: ShutdownSignal,
+    server_id: ServerId,
     tx: std::sync::mpsc::Sender<ServerStatusInfo>,
-    mut server_status: ServerStatusInfo,
+    server_status: ServerStatusInfo,
+    s7: S7Session,
 ) -> std::io::Result<()> {
-    let config = match unsafe { SERVER_CONFIG.server.get(server_number) } {
+    // A copy, so changes made while the server runs can't pull it out from under us
+    let config = match SERVER_CONFIG.server(&server_id) {
         Some(cfg) => cfg,
         None => {
-            log(&format!("ERROR: No server config for index {}", server_number));
+            log_error(&format!("No server config for id {}", server_id));
             return Ok(());
         }
     };
 
-    let mut ip_address = config.ip_address.to_string();
-    let mut port = config.port.to_string();
-    let mut address = format!("{}:{}", ip_address, port);
+    if let Err(e) = config.tsaps() {
+        log_error(&format!("Invalid TSAP for {}: {}", config.name, e));
+        return Ok(());
+    }
+    for peer in config.allowed_peers.iter().filter(|p| p.trim().parse::<std::net::IpAddr>().is_err()) {
+        log_warn(&format!("Allowed peer '{}' for {} isn't an IP address, it will never match.", peer, config.name));
+    }
+
+    // Sequence numbers are tracked across connections, so a reconnect can't hide lost packets.
+    let last_stored_sequence = if config.sequence_numbers {
+        connect_to_db().ok().and_then(|c| last_sequence(&c, &config.name).ok().flatten())
+    } else {
+        None
+    };
+    let shared = ServerShared {
+        sequence: Arc::new(std::sync::Mutex::new(SequenceTracker::new(last_stored_sequence))),
+        comms: Arc::new(std::sync::Mutex::new(CommsMonitor::new())),
+        stats: Arc::new(std::sync::Mutex::new(TrafficStats::default())),
+        peers: Arc::new(std::sync::Mutex::new([PeerStatus::default(); MAX_PEERS])),
+        clock_drift_ms: Arc::new(std::sync::Mutex::new(None)),
+        s7: s7.clone(),
+    };
+
+    let watchdog = tokio::spawn(watch_comms(config.name.clone(), config.keepalive_timeout(), shared.comms.clone(), shutdown.clone()));
+    let clock_watch = (config.clock_check_secs > 0).then(|| tokio::spawn(watch_clock(config.clone(), shared.clone(), shutdown.clone())));
+    let result = match (config.transport, config.connection_mode) {
+        (Transport::Udp, _) => run_udp(&config, shutdown, tx, server_status, shared).await,
+        (Transport::S7Poll, _) => run_s7_poll(&config, shutdown, tx, server_status, shared).await,
+        (_, ConnectionMode::Listen) => run_listener(&config, shutdown, tx, server_status, shared).await,
+        (_, ConnectionMode::Connect) => run_client(&config, shutdown, tx, server_status, shared).await,
+    };
+    watchdog.abort();
+    if let Some(clock_watch) = clock_watch {
+        clock_watch.abort();
+    }
+    s7.disconnect().await;
+    result
+}
+
+// Stores a comms lost event once the PLC has been silent for longer than the keepalive timeout,
+// whether it's still connected or not. The matching comms restored event is stored by handle_frame.
+async fn watch_comms(
+    name: String,
+    timeout: Duration,
+    comms: Arc<std::sync::Mutex<CommsMonitor>>,
+    mut shutdown: ShutdownSignal,
+) {
+    let conn = match connect_to_db() {
+        Ok(conn) => conn,
+        Err(e) => {
+            log_error(&format!("Comms watchdog for {} can't open the database: {}", name, e));
+            return;
+        }
+    };
+    loop {
+        let deadline = comms.lock().unwrap().deadline(timeout);
+        tokio::select! {
+            _ = tokio::time::sleep_until(deadline.into()) => {
+                let silent = comms.lock().unwrap().check(timeout);
+                if let Some(silent) = silent {
+                    log_warn(&format!("Communication with {} lost, nothing received for {} s.", name, silent.as_secs()));
+                    let event = EventDataPacket::internal(PACKET_CODE_COMMS_LOST, vec![timeout.as_secs() as u32]);
+                    store(&conn, &event, &name);
+                }
+            }
+            _ = shutdown.recv() => break,
+        }
+    }
+}
+
+// Reads the PLC's clock every clock_check_secs while the PLC is connected, stores the drift, and
+// stores a clock drift event when it goes past max_clock_drift_ms. With more than one connection
+// (e.g. a redundant pair) the first connected PLC is checked.
+async fn watch_clock(config: ServerEntry, shared: ServerShared, mut shutdown: ShutdownSignal) {
+    let conn = match connect_to_db() {
+        Ok(conn) => conn,
+        Err(e) => {
+            log_error(&format!("Clock check for {} can't open the database: {}", config.name, e));
+            return;
+        }
+    };
+    let interval = Duration::from_secs(config.clock_check_secs.max(MIN_CLOCK_CHECK_SECS));
+    let mut drift_monitor = DriftMonitor::new(config.max_clock_drift_ms);
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = shutdown.recv() => break,
+        }
+        let peer = shared.peers.lock().unwrap().iter().find(|p| p.connected).map(|p| p.ip_addr());
+        let Some(ip) = peer else {
+            continue;
+        };
+
+        let reading = shared.s7.with_client(ip.to_string(), config.rack, config.slot, |client| {
+            let before = Local::now();
+            let plc_time = comms_s7::read_clock(client)?;
+            let after = Local::now();
+            Ok((plc_time, clock_drift::drift_ms(plc_time, before, after)))
+        }).await;
+        let (plc_time, drift) = match reading {
+            Ok(reading) => reading,
+            Err(e) => {
+                log_warn(&format!("Can't read the clock of {} ({}): {}", config.name, ip, e));
+                continue;
+            }
+        };
 
-    log(&format!("Attempting to bind to {}", address)); 
-    let listener = loop {
-        match TcpListener::bind(&address).await {
-            Ok(l) => break l, // break with the listener
+        if debug_logging() { log(&format!("Clock drift for {}: {} ms", config.name, drift)); }
+        *shared.clock_drift_ms.lock().unwrap() = Some(drift);
+        if let Err(e) = store_clock_drift(&conn, &plc_time, drift, &config.name) {
+            log_error(&format!("Error storing clock drift for {}: {}", config.name, e));
+        }
+        if drift_monitor.check(drift) {
+            log_warn(&format!("The clock of {} is {} ms out, more than the {} ms allowed.", config.name, drift, config.max_clock_drift_ms));
+            let mut event = EventDataPacket::internal(PACKET_CODE_CLOCK_DRIFT, vec![drift as i32 as u32]);
+            event.values = vec![NamedValue { name: "drift".to_string(), value: Value::Number(drift as f64), unit: "ms".to_string() }];
+            store(&conn, &event, &config.name);
+        }
+    }
+}
+
+// Binds with `bind`, retrying with exponential backoff until it works or the server is stopped.
+// The entry is re-read before every attempt, so a corrected address is picked up without a restart.
+// Returns the bound socket and the entry it was bound with, or None if the server was stopped first.
+async fn bind_with_retry<T, F, Fut>(
+    config: &ServerEntry,
+    shutdown: &mut ShutdownSignal,
+    tx: &std::sync::mpsc::Sender<ServerStatusInfo>,
+    server_status: &mut ServerStatusInfo,
+    bind: F,
+) -> Option<(T, ServerEntry)>
+where
+    F: Fn(String) -> Fut,
+    Fut: std::future::Future<Output = std::io::Result<T>>,
+{
+    let min_delay = config.bind_retry_min_secs.max(1);
+    let mut backoff = Backoff::new(
+        Duration::from_secs(min_delay),
+        Duration::from_secs(config.bind_retry_max_secs.max(min_delay)),
+    );
+    loop {
+        let Some(current) = SERVER_CONFIG.server(&config.id) else {
+            log(&format!("{} was removed from the config, no longer trying to bind.", config.name));
+            return None;
+        };
+        let address = format!("{}:{}", current.ip_address, current.port);
+        log(&format!("Attempting to bind to {}", address));
+        match bind(address.clone()).await {
+            Ok(bound) => {
+                if server_status.bind_failed {
+                    server_status.bind_failed = false;
+                    server_status.set_last_error("");
+                    server_status.new_data = true;
+                    let _ = tx.send(*server_status);
+                }
+                return Some((bound, current));
+            }
             Err(e) => {
-                log(&format!("Failed to bind to {}: {}. Aborting connection.", address, e));
-                // TODO: Can we stop the server from here?
-                // Removed. Attempt another start in 10 seconds with new data.
-                // Doesn't retry:
-                /*server_status.new_data = true; // Notify UI about the retry
-                tokio::time::sleep(Duration::from_secs(10)).await;
-                // Reload config in case we changed it in the meantime
-                ip_address = config.ip_address.to_string();
-                port = config.port.to_string();
-                address = format!("{}:{}", ip_address, port);*/
+                let delay = backoff.next_delay();
+                log_warn(&format!("Failed to bind to {}: {}. Retrying in {} s.", address, e, delay.as_secs()));
+                // Let the operator see why the server isn't listening
+                server_status.bind_failed = true;
+                server_status.set_last_error(&format!("Bind to {} failed: {}", address, e));
+                server_status.new_data = true;
+                let _ = tx.send(*server_status);
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = shutdown.recv() => {
+                        if debug_logging() { log("Shutdown signal received, no longer trying to bind."); }
+                        return None;
+                    }
+                }
             }
         }
+    }
+}
+
+// Waits for the PLC's TCON to connect to us.
+async fn run_listener(
+    config: &ServerEntry,
+    mut shutdown: ShutdownSignal,
+    tx: std::sync::mpsc::Sender<ServerStatusInfo>,
+    mut server_status: ServerStatusInfo,
+    shared: ServerShared,
+) -> std::io::Result<()> {
+    let Some((listener, config)) = bind_with_retry(config, &mut shutdown, &tx, &mut server_status, |address| async move {
+        TcpListener::bind(address).await
+    }).await else {
+        return Ok(());
     };
     // We don't get here until the listener is successfully connected.
-    log(&format!("Server listening on {}:{}", ip_address, port)); 
+    server_status.listening_port = listener.local_addr().map(|a| a.port()).unwrap_or(config.port);
+    server_status.new_data = true;
+    let _ = tx.send(server_status);
+    log(&format!("Server listening on {}:{} ({:?} framing)", config.ip_address, server_status.listening_port, config.framing)); 
   
     loop {
         let mut server_status = server_status; // Clone the server status for each connection
@@ -519,125 +1100,1011 @@ pub async fn run_server(
         tokio::select! {
             accept_result = listener.accept() => {
                 match accept_result {
-                    Ok((mut socket, addr)) => {
-                        //let (mut socket, addr) = listener.accept().await?;
-                        if DEBUG { log(&format!("New connection from {}", addr)); }
-                        // Update server status with peer IP
-                        let peer_ip_str = addr.ip().to_string();
-                        if let Ok(ipv4) = peer_ip_str.parse::<std::net::Ipv4Addr>() {
-                            let octets = ipv4.octets();
-                            server_status.peer_ip = [0; 16];
-                            server_status.peer_ip[0..4].copy_from_slice(&octets);
-                        } else if let Ok(ipv6) = peer_ip_str.parse::<std::net::Ipv6Addr>() {
-                            server_status.peer_ip = ipv6.octets();
+                    Ok((socket, addr)) => {
+                        if !config.allows_peer(addr.ip()) {
+                            log_warn(&format!("Rejected connection to {} from {}, it isn't an allowed peer.", config.name, addr));
+                            continue; // Dropping the socket closes it
                         }
-                        server_status.is_connected = true;
+                        if debug_logging() { log(&format!("New connection from {}", addr)); }
+                        // Update server status with peer IP
+                        server_status.set_peer_ip(addr.ip());
                         server_status.new_data = true; // Notify UI about new connection
-                        // Initialize SQLite connection -- each connection needs its own database connection.
-                        let conn = connect_to_db().expect("Failed to connect to database");
-                        let shutdown_notify = shutdown_notify.clone(); // Clone for each task
+                        let ctx = match PacketContext::new(&config, &shared) {
+                            Ok(ctx) => ctx,
+                            Err(e) => {
+                                log_error(&format!("Can't open the database for {}, closing the connection from {}: {}", config.name, addr, e));
+                                continue;
+                            }
+                        };
+                        let shutdown = shutdown.clone(); // Clone for each task
+                        // Each connection gets its own decoder, partial frames are never shared between connections.
+                        let decoder = StreamDecoder::passive(&config);
                         tokio::spawn(async move {
-                            let mut buffer = [0u8; 512];
-                            loop {
-                                tokio::select! {
-                                    read_result = socket.read(&mut buffer) => {
-                                        match read_result {
-                                            Ok(0) => {
-                                                log("Connection closed by client.");
-                                                server_status.is_connected = false;
-                                                break;
-                                            }
-                                            Ok(size) => {
-                                                log(&format!("Received {} bytes: {:?}", size, &buffer[..size]));
-                                                server_status.is_running = true;
-                                                server_status.is_alive = true;
-                                                server_status.new_data = true; 
-                                                server_status.last_packet_time = Local::now().timestamp_millis() as u64;
-                                                // Notify the UI thread about new data
-                                                let _ = tx.send(server_status.clone());
-                                                // Deserialize the event data packet
-                                                if let Some(packet) = parse_event_data_packet(&buffer[..size]) {
-                                                    log(&format!("Parsed packet: sender={}, data_type={}, plc_packet_code={}, data={:?}",
-                                                                &config.name, packet.data_type, packet.plc_packet_code, packet.data));
-                                                    // Check for special packets we want to process rather than store
-                                                    match packet.data_type {                                                    
-                                                        1 => { // System Packets
-                                                            match packet.plc_packet_code {
-                                                                22 => {
-                                                                    log("Received config packet (code 22), updating server config.");
-                                                                    // Update with new values from the packet
-                                                                    //process_config_packet(&packet);
-                                                                }
-                                                                41 | 42 => {
-                                                                    // Downtime start/stop. Put the data into the database
-                                                                    let result = store_packet(&conn, &packet, &config.name);
-                                                                    if result.is_err() {
-                                                                        if DEBUG { log(&format!("Error storing packet in database: {:?}", result)); }
-                                                                        // TODO: Close the connection when we have SQL INSERT errors.
-                                                                    }
-                                                                }
-                                                                _ => {
-                                                                    log(&format!("Received unknown system packet (code {})", packet.plc_packet_code));
-                                                                }
-                                                            }
-                                                        }
-                                                        12 => { // Keep alive packet that we do no need to store
-                                                            ;
-                                                        }
-                                                        _ => { // Other
-                                                            // Put the data into the database
-                                                            let result = store_packet(&conn, &packet, &config.name); 
-                                                            if result.is_err() {
-                                                                if DEBUG { log(&format!("Error storing packet in database: {:?}", result)); }
-                                                                // TODO: Close the connection when we have SQL INSERT errors.
-                                                            }
-                                                        }
-                                                    }
-                                                } else {
-                                                    log("Failed to parse event data packet.");
-                                                }
-
-                                                // Send ACK or echo back
-                                                if let Err(e) = socket.write_all(b"ACK").await {
-                                                    if DEBUG { log(&format!("Failed to send ACK: {}", e)); }
-                                                    break;
-                                                } else {
-                                                    if DEBUG { log("ACK sent to client."); }
-                                                }
-                                            }
-                                            Err(e) => {
-                                                if DEBUG { log(&format!("Read error: {}", e)); }
-                                                break;
-                                            }
-                                        }
-                                    }
-                                    _ = tokio::time::sleep(Duration::from_secs(30)) => {
-                                        if DEBUG { log("No data received for 30 seconds.") };
-                                        server_status.is_alive = false;
-                                        // Notify the UI thread about the status change
-                                        server_status.new_data = true;
-                                        let _ = tx.send(server_status.clone());
-                                    }
-                                    _ = shutdown_notify.notified() => {
-                                        if DEBUG { log("Shutdown signal received, closing connection."); }
-                                        break;
+                            handle_connection(socket, ctx, decoder, tx, server_status, shutdown).await;
+                            if debug_logging() { log("Ending connection handler."); }
+                        });
+                    }
+                    Err(e) => {
+                        if debug_logging() { log(&format!("Failed to accept connection: {}", e)); }
+                        break;
+                    }
+                }
+            }
+            _ = shutdown.recv() => {
+                if debug_logging() { log("Shutdown signal received, stopping server."); }
+                break;
+            }
+        }
+    }
+    Ok(())  
+}
+
+// Connects out to a PLC whose TCON is configured as the passive partner, and reconnects when it drops.
+async fn run_client(
+    config: &ServerEntry,
+    mut shutdown: ShutdownSignal,
+    tx: std::sync::mpsc::Sender<ServerStatusInfo>,
+    mut server_status: ServerStatusInfo,
+    shared: ServerShared,
+) -> std::io::Result<()> {
+    let address = format!("{}:{}", config.ip_address, config.port);
+    let mut backoff = Backoff::new(
+        Duration::from_secs(RECONNECT_DELAY_MIN_SECS),
+        Duration::from_secs(RECONNECT_DELAY_MAX_SECS),
+    );
+
+    loop {
+        log(&format!("Connecting to PLC at {}", address));
+        let connect_result = tokio::select! {
+            result = tokio::time::timeout(Duration::from_secs(CONNECT_TIMEOUT_SECS), TcpStream::connect(&address)) => {
+                result.unwrap_or_else(|_| Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "connection timed out")))
+            }
+            _ = shutdown.recv() => {
+                if debug_logging() { log("Shutdown signal received, stopping client."); }
+                break;
+            }
+        };
+
+        match connect_result {
+            Ok(mut socket) => match PacketContext::new(config, &shared) {
+                Ok(ctx) => {
+                    log(&format!("Connected to PLC at {} ({:?} framing)", address, config.framing));
+                    backoff.reset();
+                    if let Ok(addr) = socket.peer_addr() {
+                        server_status.set_peer_ip(addr.ip());
+                    }
+                    server_status.is_connected = true;
+                    server_status.new_data = true; // Notify UI about new connection
+
+                    let (decoder, request) = StreamDecoder::active(config);
+                    if let Some(request) = request {
+                        // ISO-on-TCP needs a connection request before any data
+                        if let Err(e) = socket.write_all(&request).await {
+                            log_warn(&format!("Failed to send ISO connection request to {}: {}", address, e));
+                        }
+                    }
+                    let stopped = handle_connection(socket, ctx, decoder, tx.clone(), server_status, shutdown.clone()).await;
+                    server_status.is_connected = false;
+                    server_status.is_alive = false;
+                    if stopped {
+                        break;
+                    }
+                }
+                Err(e) => log_error(&format!("Can't open the database for {}, dropping the connection to {}: {}", config.name, address, e)),
+            },
+            Err(e) => {
+                log_warn(&format!("Failed to connect to PLC at {}: {}", address, e));
+            }
+        }
+
+        let delay = backoff.next_delay();
+        log(&format!("Reconnecting to {} in {} s", address, delay.as_secs()));
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = shutdown.recv() => {
+                if debug_logging() { log("Shutdown signal received, stopping client."); }
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Reads the PLC's poll areas with S7 communication on a cycle, instead of waiting for TSEND,
+// and stores every area that changed as an event. Reconnects when nothing can be read.
+// The reads go through the server's S7Session, so snapshots, clock checks and writes share its connection.
+async fn run_s7_poll(
+    config: &ServerEntry,
+    mut shutdown: ShutdownSignal,
+    tx: std::sync::mpsc::Sender<ServerStatusInfo>,
+    mut server_status: ServerStatusInfo,
+    shared: ServerShared,
+) -> std::io::Result<()> {
+    if config.poll_areas.is_empty() {
+        log_warn(&format!("{} has no <PollArea>, it will connect but never store anything.", config.name));
+    }
+    let peer = config.ip_address.trim().parse().unwrap_or(std::net::Ipv4Addr::UNSPECIFIED.into());
+    let interval = Duration::from_millis(config.poll_interval_ms.max(MIN_POLL_INTERVAL_MS));
+    let mut backoff = Backoff::new(
+        Duration::from_secs(RECONNECT_DELAY_MIN_SECS),
+        Duration::from_secs(RECONNECT_DELAY_MAX_SECS),
+    );
+    let Some(mut ctx) = open_packet_context(config, &shared, &mut shutdown).await else {
+        return Ok(());
+    };
+    ctx.layout = PacketLayout::default(); // Areas are framed without sequence numbers or timestamps
+    let mut changes = ChangeDetector::new(config.poll_areas.len());
+    let mut unreadable = vec![false; config.poll_areas.len()]; // So a bad area is logged once, not every cycle
+    let s7 = shared.s7.clone();
+    let ip = config.ip_address.trim().to_string();
+
+    loop {
+        log(&format!("Connecting to PLC at {} (rack {}, slot {}) for S7 polling", config.ip_address, config.rack, config.slot));
+        let connect_result = tokio::select! {
+            result = s7.with_client(ip.clone(), config.rack, config.slot, |_| Ok(())) => result,
+            _ = shutdown.recv() => {
+                if debug_logging() { log("Shutdown signal received, stopping S7 polling."); }
+                break;
+            }
+        };
+
+        match connect_result {
+            Ok(()) => {
+                log(&format!("Connected to PLC at {}, polling {} areas every {} ms", config.ip_address, config.poll_areas.len(), interval.as_millis()));
+                backoff.reset();
+                let peer_slot = ctx.claim_peer(peer).unwrap_or(0);
+                server_status.set_peer_ip(peer);
+                server_status.is_running = true;
+                server_status.new_data = true;
+                ctx.update_stats(&mut server_status, |stats| stats.connected());
+                let _ = tx.send(server_status);
+
+                let stopped = loop {
+                    let areas = config.poll_areas.clone();
+                    let cycle = s7.with_client(ip.clone(), config.rack, config.slot, move |client| {
+                        Ok(areas.iter().map(|a| comms_s7::read_area(client, a)).collect::<Vec<_>>())
+                    }).await;
+                    let reads = match cycle {
+                        Ok(reads) => reads,
+                        Err(e) => {
+                            log_warn(&format!("Lost the S7 connection to {}: {}", config.name, e));
+                            break false;
+                        }
+                    };
+
+                    // One bad area (e.g. a DB that isn't there) is skipped, but when nothing
+                    // can be read the connection has gone
+                    let mut read_any = false;
+                    for (i, (area, read)) in config.poll_areas.iter().zip(reads).enumerate() {
+                        match read {
+                            Ok(bytes) => {
+                                read_any = true;
+                                if std::mem::take(&mut unreadable[i]) {
+                                    log(&format!("DB{}.{} can be read from {} again.", area.db, area.start, config.name));
+                                }
+                                if changes.changed(i, &bytes) {
+                                    if debug_logging() { log(&format!("DB{}.{} changed: {:?}", area.db, area.start, bytes)); }
+                                    ctx.update_peer(peer_slot, &mut server_status, |p| p.packets += 1);
+                                    // No one to send a response to, but a change that wasn't stored is read again
+                                    let (status, _) = store_frame(&ctx, &area.frame(&bytes), &mut server_status);
+                                    if !status.is_ok() {
+                                        changes.forget(i);
                                     }
                                 }
                             }
-                            if DEBUG { log("Ending connection handler."); }
+                            Err(e) => {
+                                if !std::mem::replace(&mut unreadable[i], true) {
+                                    log_warn(&format!("Failed to read DB{}.{} ({} bytes) from {}, skipping it: {}", area.db, area.start, area.size, config.name, e));
+                                }
+                            }
+                        }
+                    }
+                    if !read_any && !config.poll_areas.is_empty() {
+                        log_warn(&format!("Couldn't read any area from {}, reconnecting.", config.name));
+                        s7.disconnect().await;
+                        break false;
+                    }
+                    // A successful read is as good as a keepalive
+                    let now = Local::now().timestamp_millis() as u64;
+                    ctx.packet_received();
+                    server_status.last_packet_time = now;
+                    server_status.new_data = true;
+                    ctx.update_peer(peer_slot, &mut server_status, |p| {
+                        p.alive = true;
+                        p.last_packet_time = now;
+                    });
+                    let _ = tx.send(server_status);
+
+                    tokio::select! {
+                        _ = tokio::time::sleep(interval) => {}
+                        _ = shutdown.recv() => {
+                            if debug_logging() { log("Shutdown signal received, stopping S7 polling."); }
+                            break true;
+                        }
+                    }
+                };
+
+                // Let the UI know the PLC has gone
+                ctx.update_peer(peer_slot, &mut server_status, |p| {
+                    p.connected = false;
+                    p.alive = false;
+                });
+                ctx.update_stats(&mut server_status, |stats| stats.disconnected());
+                server_status.new_data = true;
+                let _ = tx.send(server_status);
+                if stopped {
+                    break;
+                }
+            }
+            Err(e) => {
+                log_warn(&format!("Failed to connect to PLC at {}: {}", config.ip_address, e));
+            }
+        }
+
+        let delay = backoff.next_delay();
+        log(&format!("Reconnecting to {} in {} s", config.ip_address, delay.as_secs()));
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = shutdown.recv() => {
+                if debug_logging() { log("Shutdown signal received, stopping S7 polling."); }
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Receives TUSEND datagrams. There is no connection, so the PLC counts as connected
+// while datagrams keep arriving, and the last sender is shown as the peer.
+async fn run_udp(
+    config: &ServerEntry,
+    mut shutdown: ShutdownSignal,
+    tx: std::sync::mpsc::Sender<ServerStatusInfo>,
+    mut server_status: ServerStatusInfo,
+    shared: ServerShared,
+) -> std::io::Result<()> {
+    let Some((socket, config)) = bind_with_retry(config, &mut shutdown, &tx, &mut server_status, |address| async move {
+        UdpSocket::bind(address).await
+    }).await else {
+        return Ok(());
+    };
+    server_status.listening_port = socket.local_addr().map(|a| a.port()).unwrap_or(config.port);
+    server_status.new_data = true;
+    let _ = tx.send(server_status);
+    log(&format!("Server receiving UDP on {}:{}", config.ip_address, server_status.listening_port));
+
+    let Some(ctx) = open_packet_context(&config, &shared, &mut shutdown).await else {
+        return Ok(());
+    };
+    let mut buffer = [0u8; MAX_PACKET_SIZE];
+    loop {
+        tokio::select! {
+            recv_result = socket.recv_from(&mut buffer) => {
+                match recv_result {
+                    Ok((size, addr)) => {
+                        if debug_logging() { log(&format!("Received {} byte datagram from {}: {:?}", size, addr, &buffer[..size])); }
+                        if !config.allows_peer(addr.ip()) {
+                            log_warn(&format!("Ignored datagram to {} from {}, it isn't an allowed peer.", config.name, addr));
+                            continue;
+                        }
+                        ctx.expire_peers(&mut server_status);
+                        let Some((slot, new_peer)) = ctx.find_or_claim_peer(addr.ip()) else {
+                            log_warn(&format!("Ignored datagram to {} from {}, it already has {} peers.", config.name, addr, MAX_PEERS));
+                            continue;
+                        };
+                        if new_peer {
+                            ctx.update_stats(&mut server_status, |stats| stats.connected());
+                        }
+                        let now = Local::now().timestamp_millis() as u64;
+                        server_status.set_peer_ip(addr.ip());
+                        server_status.is_running = true;
+                        server_status.new_data = true;
+                        server_status.last_packet_time = now;
+                        ctx.update_peer(slot, &mut server_status, |p| {
+                            p.alive = true;
+                            p.last_packet_time = now;
+                            p.packets += 1;
                         });
+
+                        // Each datagram is exactly one packet
+                        let response = handle_frame(&ctx, &buffer[..size], &mut server_status);
+                        if let Err(e) = socket.send_to(&response, addr).await {
+                            if debug_logging() { log(&format!("Failed to send response to {}: {}", addr, e)); }
+                        } else {
+                            if debug_logging() { log(&format!("Response sent to {}: {:?}", addr, response)); }
+                        }
+                        // Notify the UI thread about new data
+                        let _ = tx.send(server_status);
                     }
                     Err(e) => {
-                        if DEBUG { log(&format!("Failed to accept connection: {}", e)); }
+                        // e.g. an ICMP port unreachable from an earlier response, keep listening
+                        if debug_logging() { log(&format!("UDP receive error: {}", e)); }
+                    }
+                }
+            }
+            _ = tokio::time::sleep(ctx.keepalive_timeout) => {
+                if debug_logging() { log(&format!("No datagram received for {} seconds.", ctx.keepalive_timeout.as_secs())) };
+                ctx.expire_peers(&mut server_status);
+                server_status.new_data = true;
+                let _ = tx.send(server_status);
+            }
+            _ = shutdown.recv() => {
+                if debug_logging() { log("Shutdown signal received, stopping UDP server."); }
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+// For a server with one PacketContext for all its packets (UDP, S7 polling): keeps trying to open the
+// database, backing off, rather than leaving the server running without it. None if it's stopped first.
+async fn open_packet_context(config: &ServerEntry, shared: &ServerShared, shutdown: &mut ShutdownSignal) -> Option<PacketContext> {
+    let mut backoff = Backoff::new(
+        Duration::from_secs(RECONNECT_DELAY_MIN_SECS),
+        Duration::from_secs(RECONNECT_DELAY_MAX_SECS),
+    );
+    loop {
+        let e = match PacketContext::new(config, shared) {
+            Ok(ctx) => return Some(ctx),
+            Err(e) => e,
+        };
+        let delay = backoff.next_delay();
+        log_error(&format!("Can't open the database for {}, trying again in {} s: {}", config.name, delay.as_secs(), e));
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = shutdown.recv() => return None,
+        }
+    }
+}
+
+// Everything needed to process the packets from one connection.
+struct PacketContext {
+    conn: Connection,
+    name: String,
+    layout: PacketLayout,
+    response_format: ResponseFormat,
+    keepalive_timeout: Duration,
+    // From ServerShared
+    sequence: Arc<std::sync::Mutex<SequenceTracker>>,
+    comms: Arc<std::sync::Mutex<CommsMonitor>>,
+    stats: Arc<std::sync::Mutex<TrafficStats>>,
+    peers: Arc<std::sync::Mutex<[PeerStatus; MAX_PEERS]>>,
+    clock_drift_ms: Arc<std::sync::Mutex<Option<i64>>>, // Set by watch_clock
+    s7: S7Session,
+    rack: u16, // For S7 connections back to the PLC
+    slot: u16,
+    read_downtime_db: bool,
+    downtime_schema: String,
+    snapshot_triggers: Vec<SnapshotTrigger>,
+}
+
+impl PacketContext {
+    fn new(config: &ServerEntry, shared: &ServerShared) -> rusqlite::Result<Self> {
+        Ok(PacketContext {
+            // Each connection needs its own database connection.
+            conn: connect_to_db()?,
+            name: config.name.clone(),
+            layout: config.packet_layout(),
+            response_format: config.response_format,
+            keepalive_timeout: config.keepalive_timeout(),
+            sequence: shared.sequence.clone(),
+            comms: shared.comms.clone(),
+            stats: shared.stats.clone(),
+            peers: shared.peers.clone(),
+            clock_drift_ms: shared.clock_drift_ms.clone(),
+            s7: shared.s7.clone(),
+            rack: config.rack,
+            slot: config.slot,
+            read_downtime_db: config.read_downtime_db,
+            downtime_schema: config.downtime_schema.clone(),
+            snapshot_triggers: config.snapshot_triggers.clone(),
+        })
+    }
+
+    // Stores a comms restored event if the PLC had been silent for longer than the keepalive timeout
+    fn packet_received(&self) {
+        let outage = self.comms.lock().unwrap().packet_received();
+        if let Some(outage) = outage {
+            log(&format!("Communication with {} restored after {} s.", self.name, outage.as_secs()));
+            let event = EventDataPacket::internal(PACKET_CODE_COMMS_RESTORED, vec![outage.as_secs() as u32]);
+            store(&self.conn, &event, &self.name);
+        }
+    }
+
+    // Updates the server's counters and copies them into this connection's status
+    fn update_stats(&self, server_status: &mut ServerStatusInfo, update: impl FnOnce(&mut TrafficStats)) {
+        update(&mut self.stats.lock().unwrap());
+        self.sync_status(server_status);
+    }
+
+    // Updates one peer's state and copies every peer into this connection's status
+    fn update_peer(&self, slot: usize, server_status: &mut ServerStatusInfo, update: impl FnOnce(&mut PeerStatus)) {
+        update(&mut self.peers.lock().unwrap()[slot]);
+        self.sync_status(server_status);
+    }
+
+    // Each connection has its own copy of the status, so the parts shared with other
+    // connections are copied in before it goes to the UI.
+    fn sync_status(&self, server_status: &mut ServerStatusInfo) {
+        server_status.stats = *self.stats.lock().unwrap();
+        let peers = *self.peers.lock().unwrap();
+        server_status.peers = peers;
+        server_status.is_connected = peers.iter().any(|p| p.connected);
+        server_status.is_alive = peers.iter().any(|p| p.alive);
+        server_status.clock_drift_ms = *self.clock_drift_ms.lock().unwrap();
+    }
+
+    // Finds a free slot for a new connection, preferring the one this peer used last time.
+    fn claim_peer(&self, ip: std::net::IpAddr) -> Option<usize> {
+        let ip = ip_to_bytes(ip);
+        let mut peers = self.peers.lock().unwrap();
+        let slot = peers.iter().position(|p| p.ip == ip && !p.connected)
+            .or_else(|| peers.iter().position(|p| !p.connected))?;
+        let packets = if peers[slot].ip == ip { peers[slot].packets } else { 0 };
+        peers[slot] = PeerStatus {
+            ip,
+            connected: true,
+            alive: false,
+            connected_since: Local::now().timestamp_millis() as u64,
+            last_packet_time: 0,
+            packets,
+        };
+        Some(slot)
+    }
+
+    // UDP has no connections, so a sender keeps its slot until it goes quiet.
+    // Returns the slot, and true if the sender is new.
+    fn find_or_claim_peer(&self, ip: std::net::IpAddr) -> Option<(usize, bool)> {
+        let bytes = ip_to_bytes(ip);
+        let existing = self.peers.lock().unwrap().iter().position(|p| p.ip == bytes && p.connected);
+        match existing {
+            Some(slot) => Some((slot, false)),
+            None => self.claim_peer(ip).map(|slot| (slot, true)),
+        }
+    }
+
+    // Marks UDP senders that have gone quiet as disconnected.
+    fn expire_peers(&self, server_status: &mut ServerStatusInfo) {
+        let now = Local::now().timestamp_millis() as u64;
+        let timeout = self.keepalive_timeout.as_millis() as u64;
+        let mut expired = 0;
+        for peer in self.peers.lock().unwrap().iter_mut().filter(|p| p.connected) {
+            if now.saturating_sub(peer.last_packet_time) >= timeout {
+                peer.connected = false;
+                peer.alive = false;
+                expired += 1;
+            }
+        }
+        self.update_stats(server_status, |stats| {
+            for _ in 0..expired {
+                stats.disconnected();
+            }
+        });
+    }
+}
+
+// State that outlives a single connection, so reconnects can't hide lost packets or outages.
+#[derive(Clone)]
+struct ServerShared {
+    sequence: Arc<std::sync::Mutex<SequenceTracker>>,
+    comms: Arc<std::sync::Mutex<CommsMonitor>>,
+    stats: Arc<std::sync::Mutex<TrafficStats>>,
+    peers: Arc<std::sync::Mutex<[PeerStatus; MAX_PEERS]>>,
+    clock_drift_ms: Arc<std::sync::Mutex<Option<i64>>>,
+    s7: S7Session,
+}
+
+// Splits what arrives on a TCP connection into packets.
+enum StreamDecoder {
+    Tcp(FrameDecoder),
+    Iso(IsoSession),
+}
+
+impl StreamDecoder {
+    // For a connection the PLC opened
+    fn passive(config: &ServerEntry) -> Self {
+        match config.transport {
+            Transport::IsoOnTcp => {
+                let (local_tsap, remote_tsap) = config.tsaps().unwrap_or_default();
+                StreamDecoder::Iso(IsoSession::listen(local_tsap, remote_tsap))
+            }
+            _ => StreamDecoder::Tcp(FrameDecoder::new(config.framing, config.record_length)),
+        }
+    }
+
+    // For a connection we opened, with anything that has to be sent before the PLC will send data
+    fn active(config: &ServerEntry) -> (Self, Option<Vec<u8>>) {
+        match config.transport {
+            Transport::IsoOnTcp => {
+                let (local_tsap, remote_tsap) = config.tsaps().unwrap_or_default();
+                let (session, request) = IsoSession::connect(local_tsap, remote_tsap);
+                (StreamDecoder::Iso(session), Some(request))
+            }
+            _ => (Self::passive(config), None),
+        }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        match self {
+            StreamDecoder::Tcp(decoder) => decoder.push(bytes),
+            StreamDecoder::Iso(session) => session.push(bytes),
+        }
+    }
+
+    fn pending(&self) -> usize {
+        match self {
+            StreamDecoder::Tcp(decoder) => decoder.pending(),
+            StreamDecoder::Iso(session) => session.pending(),
+        }
+    }
+
+    // Plain TCP only ever gives packets, ISO-on-TCP also has its own handshake and disconnect.
+    fn next_frame(&mut self) -> Result<Option<IsoFrame>, &'static str> {
+        match self {
+            StreamDecoder::Tcp(decoder) => Ok(decoder.next_frame()?.map(IsoFrame::Data)),
+            StreamDecoder::Iso(session) => session.next_frame(),
+        }
+    }
+
+    fn wrap_response(&self, response: Vec<u8>) -> Vec<u8> {
+        match self {
+            StreamDecoder::Tcp(_) => response,
+            StreamDecoder::Iso(_) => wrap_data(&response),
+        }
+    }
+}
+
+// Reads from one PLC connection until it closes, splitting the stream into packets.
+// Returns true if it closed because the server is stopping.
+async fn handle_connection(
+    mut socket: TcpStream,
+    ctx: PacketContext,
+    mut decoder: StreamDecoder,
+    tx: std::sync::mpsc::Sender<ServerStatusInfo>,
+    mut server_status: ServerStatusInfo,
+    mut shutdown: ShutdownSignal,
+) -> bool {
+    let mut buffer = [0u8; MAX_PACKET_SIZE];
+    let mut stopped = false;
+    let peer = match socket.peer_addr() {
+        Ok(addr) => addr.ip(),
+        Err(_) => std::net::Ipv4Addr::UNSPECIFIED.into(),
+    };
+    let Some(slot) = ctx.claim_peer(peer) else {
+        log_warn(&format!("Closing connection from {} to {}, it already has {} connections.", peer, ctx.name, MAX_PEERS));
+        return false;
+    };
+    ctx.update_stats(&mut server_status, |stats| stats.connected());
+    let _ = tx.send(server_status);
+    'connection: loop {
+        tokio::select! {
+            read_result = socket.read(&mut buffer) => {
+                match read_result {
+                    Ok(0) => {
+                        if decoder.pending() > 0 {
+                            log(&format!("Connection closed by client with {} bytes of an incomplete packet.", decoder.pending()));
+                        } else {
+                            log("Connection closed by client.");
+                        }
+                        break;
+                    }
+                    Ok(size) => {
+                        if debug_logging() { log(&format!("Received {} bytes: {:?}", size, &buffer[..size])); }
+                        let now = Local::now().timestamp_millis() as u64;
+                        server_status.is_running = true;
+                        server_status.new_data = true; 
+                        server_status.last_packet_time = now;
+                        ctx.update_peer(slot, &mut server_status, |p| {
+                            p.alive = true;
+                            p.last_packet_time = now;
+                        });
+
+                        decoder.push(&buffer[..size]);
+                        loop {
+                            let frame = match decoder.next_frame() {
+                                Ok(Some(frame)) => frame,
+                                Ok(None) => break, // Wait for the rest of the packet
+                                Err(e) => {
+                                    log_warn(&format!("Framing error from {}: {}. Closing connection.", ctx.name, e));
+                                    ctx.update_stats(&mut server_status, |stats| stats.parse_errors += 1);
+                                    break 'connection;
+                                }
+                            };
+                            let response = match frame {
+                                IsoFrame::Data(packet) => {
+                                    ctx.update_peer(slot, &mut server_status, |p| p.packets += 1);
+                                    decoder.wrap_response(handle_frame(&ctx, &packet, &mut server_status))
+                                }
+                                IsoFrame::Reply(reply) => reply,
+                                IsoFrame::Disconnect => {
+                                    log(&format!("{} closed the ISO connection.", ctx.name));
+                                    break 'connection;
+                                }
+                            };
+
+                            // Send a response for every packet
+                            if let Err(e) = socket.write_all(&response).await {
+                                if debug_logging() { log(&format!("Failed to send response: {}", e)); }
+                                break 'connection;
+                            } else {
+                                if debug_logging() { log(&format!("Response sent to client: {:?}", response)); }
+                            }
+                        }
+                        // Notify the UI thread about new data
+                        let _ = tx.send(server_status.clone());
+                    }
+                    Err(e) => {
+                        if debug_logging() { log(&format!("Read error: {}", e)); }
                         break;
                     }
                 }
             }
-            _ = shutdown_notify.notified() => {
-                if DEBUG { log("Shutdown signal received, stopping server."); }
+            _ = tokio::time::sleep(ctx.keepalive_timeout) => {
+                if debug_logging() { log(&format!("No data received for {} seconds.", ctx.keepalive_timeout.as_secs())) };
+                ctx.update_peer(slot, &mut server_status, |p| p.alive = false);
+                // Notify the UI thread about the status change
+                server_status.new_data = true;
+                let _ = tx.send(server_status.clone());
+            }
+            _ = shutdown.recv() => {
+                if debug_logging() { log("Shutdown signal received, closing connection."); }
+                stopped = true;
                 break;
             }
         }
     }
-    Ok(())  
-}
\ No newline at end of file
+    // Let the UI know the PLC has gone
+    ctx.update_peer(slot, &mut server_status, |p| {
+        p.connected = false;
+        p.alive = false;
+    });
+    ctx.update_stats(&mut server_status, |stats| stats.disconnected());
+    server_status.new_data = true;
+    let _ = tx.send(server_status);
+    stopped
+}
+
+// Parses a single framed packet, stores it if required, and returns the response for the PLC.
+fn handle_frame(ctx: &PacketContext, frame: &[u8], server_status: &mut ServerStatusInfo) -> Vec<u8> {
+    let (status, packet) = store_frame(ctx, frame, server_status);
+    let sequence = packet.as_ref().and_then(|p| p.sequence);
+    encode_response(ctx.response_format, status, sequence, packet.map_or(0, |p| p.plc_packet_code))
+}
+
+// Parses and stores one packet. Returns what happened, and the packet if it could be parsed.
+fn store_frame(ctx: &PacketContext, frame: &[u8], server_status: &mut ServerStatusInfo) -> (PacketStatus, Option<EventDataPacket>) {
+    ctx.packet_received();
+    ctx.update_stats(server_status, |stats| stats.packet(frame.len()));
+    // Deserialize the event data packet
+    let Some(packet) = parse_event_data_packet(frame, &ctx.layout) else {
+        log_warn(&format!("Failed to parse event data packet ({} bytes).", frame.len()));
+        ctx.update_stats(server_status, |stats| stats.parse_errors += 1);
+        return (PacketStatus::ParseError, None);
+    };
+    if debug_logging() {
+        log(&format!("Parsed packet: sender={}, data_type={}, plc_packet_code={}, sequence={:?}, data={:?}",
+                     ctx.name, packet.data_type, packet.plc_packet_code, packet.sequence, packet.data));
+    }
+    let status = process_packet(ctx, &packet, server_status);
+    ctx.update_stats(server_status, |stats| {
+        if is_keepalive_packet(&packet) { stats.keepalives += 1; }
+        if status == PacketStatus::DbError { stats.db_errors += 1; }
+        if status == PacketStatus::Stored { stats.stored += 1; }
+    });
+    if !status.is_ok() {
+        log_warn(&format!("Packet from {} not stored: {:?}", ctx.name, status));
+    }
+    (status, Some(packet))
+}
+
+fn process_packet(ctx: &PacketContext, packet: &EventDataPacket, server_status: &mut ServerStatusInfo) -> PacketStatus {
+    let conn = &ctx.conn;
+    let name = &ctx.name;
+    // Held until the packet is stored, so the connections of a redundant pair can't check
+    // the counter in between, and a packet that can't be stored leaves no trace in it
+    let mut tracker = ctx.sequence.lock().unwrap();
+    let before = tracker.clone();
+    let check = packet.sequence.map(|sequence| check_sequence(ctx, &mut tracker, packet, sequence, server_status));
+    if check == Some(SequenceCheck::Duplicate) {
+        return PacketStatus::Duplicate; // Already stored
+    }
+    // Only keepalives are sent live. Events may have been buffered by the PLC,
+    // so their timestamps would make the clock look further out than it is.
+    if is_keepalive_packet(packet) {
+        if let Some(plc_time) = packet.plc_timestamp {
+            let skew = Local::now().signed_duration_since(plc_time).num_milliseconds();
+            if debug_logging() { log(&format!("Clock skew for {}: {} ms", name, skew)); }
+            server_status.clock_skew_ms = Some(skew);
+        }
+    }
+    // Check for special packets we want to process rather than store
+    let status = match packet.data_type {                                                    
+        EVENT_TYPE_SPECIAL => { // System Packets
+            match packet.plc_packet_code {
+                PACKET_CODE_CONFIG => {
+                    log("Received config packet (code 22), updating server config.");
+                    // Update with new values from the packet
+                    let status = process_config_packet(conn, packet, name, server_status);
+                    if let Some(plc_config) = server_status.plc_config.filter(|_| ctx.read_downtime_db && status.is_ok()) {
+                        tokio::spawn(read_downtime_db(ctx.s7.clone(), name.clone(), server_status.peer_ip_addr(), ctx.rack, ctx.slot, plc_config.downtime_db, ctx.downtime_schema.clone()));
+                    }
+                    status
+                }
+                PACKET_CODE_DOWNTIME_START | PACKET_CODE_DOWNTIME_END => {
+                    // Downtime start/stop. Put the data into the database
+                    store_event(ctx, packet, server_status)
+                }
+                _ => {
+                    log_warn(&format!("Received unknown system packet (code {})", packet.plc_packet_code));
+                    PacketStatus::Accepted
+                }
+            }
+        }
+        EVENT_TYPE_KEEPALIVE => { // Keep alive packet that we do no need to store
+            PacketStatus::Accepted
+        }
+        _ => { // Other
+            // Put the data into the database
+            store_event(ctx, packet, server_status)
+        }
+    };
+    if status == PacketStatus::DbError {
+        // Forget the sequence number, otherwise the PLC's resend would be dropped as a duplicate.
+        // Any gap it showed is found again, and stored, when the resend arrives.
+        *tracker = before;
+        server_status.duplicate_packets = tracker.duplicates;
+        server_status.lost_packets = tracker.lost;
+    } else if let Some(SequenceCheck::Gap { expected, received, missing }) = check {
+        let loss = EventDataPacket::internal(PACKET_CODE_DATA_LOSS, vec![expected, received, missing]);
+        if let Err(e) = store_packet(conn, &loss, name) {
+            log_error(&format!("Error storing data loss event: {}", e));
+        }
+    }
+    status
+}
+
+// The PLC is told about database errors in the response, so it can resend the packet.
+fn store(conn: &Connection, packet: &EventDataPacket, name: &String) -> PacketStatus {
+    match store_packet(conn, packet, name) {
+        Ok(_) => PacketStatus::Stored,
+        Err(e) => {
+            log_error(&format!("Error storing packet in database: {}", e));
+            PacketStatus::DbError
+        }
+    }
+}
+
+// Stores a packet from the PLC, then reads the snapshot it triggers, if any, in the background.
+fn store_event(ctx: &PacketContext, packet: &EventDataPacket, server_status: &ServerStatusInfo) -> PacketStatus {
+    let event_id = match store_packet(&ctx.conn, packet, &ctx.name) {
+        Ok(event_id) => event_id,
+        Err(e) => {
+            log_error(&format!("Error storing packet in database: {}", e));
+            return PacketStatus::DbError;
+        }
+    };
+    if let Some(trigger) = ctx.snapshot_triggers.iter().find(|t| t.matches(packet)) {
+        read_snapshot(ctx, event_id, server_status.peer_ip_addr(), trigger.areas.clone());
+    }
+    PacketStatus::Stored
+}
+
+// Duplicates are dropped by the caller. Gaps in the sequence are stored as a data loss
+// event by the caller too, once the packet that showed the gap has been stored.
+fn check_sequence(
+    ctx: &PacketContext,
+    tracker: &mut SequenceTracker,
+    packet: &EventDataPacket,
+    sequence: u32,
+    server_status: &mut ServerStatusInfo,
+) -> SequenceCheck {
+    let result = if is_keepalive_packet(packet) {
+        tracker.check_keepalive(sequence)
+    } else {
+        tracker.check_event(sequence)
+    };
+    server_status.duplicate_packets = tracker.duplicates;
+    server_status.lost_packets = tracker.lost;
+
+    match result {
+        SequenceCheck::InOrder => {}
+        SequenceCheck::Duplicate => {
+            log(&format!("Dropped duplicate packet from {} (sequence {}).", ctx.name, sequence));
+            server_status.new_data = true;
+        }
+        SequenceCheck::Restarted => {
+            log(&format!("Sequence counter for {} restarted.", ctx.name));
+        }
+        SequenceCheck::Gap { expected, received, missing } => {
+            log_error(&format!("DATA LOSS: {} packets missing from {} (expected sequence {}, received {}).",
+                         missing, ctx.name, expected, received));
+            server_status.new_data = true;
+        }
+    }
+    result
+}
+
+fn process_config_packet(conn: &Connection, packet: &EventDataPacket, name: &String, server_status: &mut ServerStatusInfo) -> PacketStatus {
+    let Some(plc_config) = parse_config_packet(packet) else {
+        log_warn(&format!("Config packet from {} is too short ({} values).", name, packet.data.len()));
+        return PacketStatus::ParseError;
+    };
+    log(&format!("{} config: downtime DB {}, {} seconds before downtime",
+                 name, plc_config.downtime_db, plc_config.seconds_before_downtime));
+    server_status.plc_config = Some(plc_config);
+    server_status.new_data = true;
+    match store_plc_config(conn, &plc_config, name) {
+        Ok(_) => PacketStatus::Stored,
+        Err(e) => {
+            log_error(&format!("Error storing config from {}: {}", name, e));
+            PacketStatus::DbError
+        }
+    }
+}
+
+// Reads the downtime DB named in a configuration packet and stores what it holds. Fills in a downtime
+// start or end that never arrived, e.g. because the connection was down when the PLC sent it.
+// A PLC that has just reconnected may still be sending the events it buffered, so the DB is read
+// again DOWNTIME_FILL_IN_DELAY_SECS later, and nothing is filled in unless the two reads agree.
+// Runs in the background, so the PLC gets its response without waiting for the S7 connection.
+async fn read_downtime_db(s7: S7Session, name: String, ip: std::net::IpAddr, rack: u16, slot: u16, downtime_db: u32, schema: String) {
+    let Ok(db) = u16::try_from(downtime_db) else {
+        log_warn(&format!("{} announced downtime DB {}, which isn't a DB number.", name, downtime_db));
+        return;
+    };
+    let schema = match downtime_schema(&schema) {
+        Ok(schema) => schema,
+        Err(e) => {
+            log_warn(&format!("Can't read {}'s downtime DB, its {}", name, e));
+            return;
+        }
+    };
+    let Some(first) = read_downtime_snapshot(&s7, &name, ip, rack, slot, db, schema).await else { return };
+    tokio::time::sleep(Duration::from_secs(DOWNTIME_FILL_IN_DELAY_SECS)).await;
+    let Some(snapshot) = read_downtime_snapshot(&s7, &name, ip, rack, slot, db, schema).await else { return };
+
+    let conn = match connect_to_db() {
+        Ok(conn) => conn,
+        Err(e) => {
+            log_error(&format!("Error opening the database for {}'s downtime DB: {}", name, e));
+            return;
+        }
+    };
+    let filled_in = if !first.settled(&snapshot) {
+        if debug_logging() { log(&format!("{}'s downtime DB{} changed between reads, not filling in.", name, db)); }
+        None
+    } else {
+        match last_downtime_event(&conn, &name) {
+            Ok(last) => snapshot.missing_event(last, Local::now()),
+            Err(e) => {
+                log_error(&format!("Error finding {}'s last downtime event: {}", name, e));
+                None
+            }
+        }
+    };
+    if let Some(event) = &filled_in {
+        let missing = if event.plc_packet_code == PACKET_CODE_DOWNTIME_START { "start" } else { "end" };
+        log_warn(&format!("{}'s downtime DB{} says it is {}, but the downtime {} was never received. Storing one.",
+                     name, db, if snapshot.in_downtime { "down" } else { "running" }, missing));
+        if let Err(e) = store_packet(&conn, event, &name) {
+            log_error(&format!("Error storing filled in downtime {}: {}", missing, e));
+        }
+    }
+    if let Err(e) = store_downtime_snapshot(&conn, &snapshot, db, filled_in.map(|e| e.plc_packet_code), &name) {
+        log_error(&format!("Error storing {}'s downtime DB: {}", name, e));
+    }
+}
+
+async fn read_downtime_snapshot(s7: &S7Session, name: &str, ip: std::net::IpAddr, rack: u16, slot: u16, db: u16,
+                                schema: Option<&'static PayloadSchema>) -> Option<DowntimeSnapshot> {
+    let size = schema.map_or(DOWNTIME_DB_SIZE, |s| s.size());
+    let read = s7.with_client(ip.to_string(), rack, slot, move |client| {
+        comms_s7::read_data(client, db, 0, size)
+    }).await;
+    let snapshot = match read {
+        Ok(bytes) => DowntimeSnapshot::decode(&bytes, schema),
+        Err(e) => {
+            log_warn(&format!("Can't read downtime DB{} from {} at {}: {}", db, name, ip, e));
+            return None;
+        }
+    };
+    match snapshot {
+        Some(snapshot) => {
+            if debug_logging() { log(&format!("{} downtime DB{}: {:?}", name, db, snapshot)); }
+            Some(snapshot)
+        }
+        None => {
+            log_warn(&format!("Can't decode {}'s downtime DB{}, a downtime field doesn't fit in it or isn't a whole number.", name, db));
+            None
+        }
+    }
+}
+
+// Reads a snapshot trigger's DB ranges and stores them linked to the event stored as `event_id`,
+// decoded with each area's schema. One connection reads every area, so they're as close together as S7 allows.
+// Runs in the background, and counts what it stores in the server's stats so the Events tab shows it.
+fn read_snapshot(ctx: &PacketContext, event_id: i64, ip: std::net::IpAddr, areas: Vec<SnapshotArea>) {
+    let (s7, name, stats, rack, slot) = (ctx.s7.clone(), ctx.name.clone(), ctx.stats.clone(), ctx.rack, ctx.slot);
+    tokio::spawn(async move {
+        let stored = read_and_store_snapshot(s7, &name, event_id, ip, rack, slot, areas).await;
+        stats.lock().unwrap().snapshots += stored;
+    });
+}
+
+// Returns how many areas were stored
+async fn read_and_store_snapshot(s7: S7Session, name: &String, event_id: i64, ip: std::net::IpAddr, rack: u16, slot: u16, areas: Vec<SnapshotArea>) -> u64 {
+    let reads = s7.with_client(ip.to_string(), rack, slot, move |client| {
+        let reads: Vec<(SnapshotArea, Result<Vec<u8>, String>)> = areas.into_iter()
+            .map(|area| {
+                let read = comms_s7::read_data(client, area.db, area.start, area.size as usize);
+                (area, read)
+            })
+            .collect();
+        Ok(reads)
+    }).await;
+    let reads = match reads {
+        Ok(reads) => reads,
+        Err(e) => {
+            log_warn(&format!("Can't read the snapshot for {} event {} from {}: {}", name, event_id, ip, e));
+            return 0;
+        }
+    };
+
+    let conn = match connect_to_db() {
+        Ok(conn) => conn,
+        Err(e) => {
+            log_error(&format!("Error opening the database for {}'s snapshot: {}", name, e));
+            return 0;
+        }
+    };
+    let mut stored = 0;
+    for (area, read) in reads {
+        match read {
+            Ok(bytes) => {
+                let values = area.decode(&bytes);
+                match store_event_snapshot(&conn, event_id, area.db, area.start, &bytes, &values, name) {
+                    Ok(()) => stored += 1,
+                    Err(e) => log_error(&format!("Error storing snapshot of DB{}.{} for {}: {}", area.db, area.start, name, e)),
+                }
+            }
+            Err(e) => log_warn(&format!("Can't read DB{}.{} ({} bytes) from {} for a snapshot: {}", area.db, area.start, area.size, name, e)),
+        }
+    }
+    stored
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn stopping_one_server_doesnt_fire_another() {
+        let app = Arc::new(Notify::new());
+        let (stop_edger, edger_rx) = watch::channel(false);
+        let (_stop_trimmer, trimmer_rx) = watch::channel(false);
+        let mut edger = ShutdownSignal::new(app.clone(), edger_rx);
+        let mut trimmer = ShutdownSignal::new(app.clone(), trimmer_rx);
+
+        stop_edger.send(true).unwrap();
+        assert!(tokio::time::timeout(Duration::from_millis(100), edger.recv()).await.is_ok());
+        assert!(tokio::time::timeout(Duration::from_millis(100), trimmer.recv()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn app_shutdown_fires_every_server() {
+        let app = Arc::new(Notify::new());
+        let (_stop_edger, edger_rx) = watch::channel(false);
+        let (_stop_trimmer, trimmer_rx) = watch::channel(false);
+        let mut edger = ShutdownSignal::new(app.clone(), edger_rx);
+        let mut trimmer = ShutdownSignal::new(app.clone(), trimmer_rx);
+        let edger_task = tokio::spawn(async move { edger.recv().await });
+        let trimmer_task = tokio::spawn(async move { trimmer.recv().await });
+        tokio::task::yield_now().await; // Let both start waiting
+
+        app.notify_waiters();
+        assert!(tokio::time::timeout(Duration::from_secs(1), edger_task).await.is_ok());
+        assert!(tokio::time::timeout(Duration::from_secs(1), trimmer_task).await.is_ok());
+    }
+
+}
@@ -0,0 +1,71 @@
+use std::sync::RwLock;
+use once_cell::sync::Lazy;
+use tokio::sync::watch;
+use crate::comms_tcon::{ServerConfig, ServerEntry};
+use crate::constants::DEFAULT_CONFIG_PATH;
+use crate::server_id::ServerId;
+use crate::xmlhandling::load_config;
+
+// The server list, shared by the UI, the server manager and the server tasks.
+// Readers get copies, so a running server can't see an entry change half way through.
+// Every change goes through `update`, which notifies everyone that subscribed.
+pub struct ConfigStore {
+    sender: watch::Sender<ServerConfig>,
+}
+
+impl ConfigStore {
+    pub fn new(config: ServerConfig) -> Self {
+        let (sender, _) = watch::channel(config);
+        ConfigStore { sender }
+    }
+
+    pub fn get(&self) -> ServerConfig {
+        self.sender.borrow().clone()
+    }
+
+    pub fn servers(&self) -> Vec<ServerEntry> {
+        self.sender.borrow().server.clone()
+    }
+
+    pub fn server(&self, id: &ServerId) -> Option<ServerEntry> {
+        self.sender.borrow().server.iter().find(|s| &s.id == id).cloned()
+    }
+
+    pub fn server_count(&self) -> usize {
+        self.sender.borrow().server.len()
+    }
+
+    // Changes the config and notifies subscribers. Returns whatever `change` returns.
+    pub fn update<R>(&self, change: impl FnOnce(&mut ServerConfig) -> R) -> R {
+        let mut result = None;
+        self.sender.send_modify(|config| result = Some(change(config)));
+        result.expect("send_modify always runs the closure")
+    }
+
+    // Changes every time `update` is called
+    pub fn subscribe(&self) -> watch::Receiver<ServerConfig> {
+        self.sender.subscribe()
+    }
+}
+
+static CONFIG_PATH: Lazy<RwLock<String>> = Lazy::new(|| RwLock::new(DEFAULT_CONFIG_PATH.to_string()));
+
+// Where SERVER_CONFIG is loaded from and saved to. Only call before SERVER_CONFIG is first used.
+pub fn set_config_path(path: &str) {
+    *CONFIG_PATH.write().unwrap() = path.to_string();
+}
+
+pub fn config_path() -> String {
+    CONFIG_PATH.read().unwrap().clone()
+}
+
+pub static SERVER_CONFIG: Lazy<ConfigStore> = Lazy::new(|| {
+    ConfigStore::new(load_config(&config_path()).unwrap_or_else(|_| ServerConfig {
+        server: vec![
+            ServerEntry {
+                name: "Default Server".to_string(),
+                ..Default::default()
+            }
+        ]
+    }))
+});
@@ -8,6 +8,58 @@ pub const FONT_SIZE_MENU: f32 = 13.0;
 pub const FONT_SIZE_WIN_CONTROLS: f32 = 10.0;
 pub const RESIZE_HANDLE_SIZE: f64 = 5.0;
 
+// Relative to the working directory. The headless binary can be given other paths.
+pub const DEFAULT_CONFIG_PATH: &str = "config.xml";
+pub const DEFAULT_DB_PATH: &str = "event_data.db";
+pub const LOG_PATH: &str = "log.txt";
+
 pub const EVENT_TYPE_SPECIAL: u32 = 1;
+pub const EVENT_TYPE_ALARM: u32 = 9;
 pub const EVENT_TYPE_KEEPALIVE: u32 = 12;
-pub const EVENT_TYPE_PLC: u32 = 50;
\ No newline at end of file
+pub const EVENT_TYPE_PLC: u32 = 50;
+// Special event codes (data_type = EVENT_TYPE_SPECIAL)
+pub const PACKET_CODE_CONFIG: u32 = 22;
+pub const PACKET_CODE_DOWNTIME_START: u32 = 41;
+pub const PACKET_CODE_DOWNTIME_END: u32 = 42;
+
+// Used until the PLC has sent a configuration packet
+pub const DEFAULT_SECONDS_BEFORE_DOWNTIME: u32 = 120;
+
+// Special event codes generated by this application
+pub const PACKET_CODE_DATA_LOSS: u32 = 90; // data = [expected sequence, received sequence, missing count]
+pub const PACKET_CODE_COMMS_LOST: u32 = 91; // data = [keepalive timeout in seconds]
+pub const PACKET_CODE_COMMS_RESTORED: u32 = 92; // data = [seconds since the last packet]
+pub const PACKET_CODE_CLOCK_DRIFT: u32 = 93; // data = [PLC time minus PC time in ms, as a DINT]
+pub const PACKET_CODE_CLOCK_SET: u32 = 94; // data = [drift in ms just before the PC's time was written, as a DINT]
+
+// Active (client) connections to the PLC
+pub const CONNECT_TIMEOUT_SECS: u64 = 10;
+pub const RECONNECT_DELAY_MIN_SECS: u64 = 1;
+pub const RECONNECT_DELAY_MAX_SECS: u64 = 60;
+
+// S7 polling (Transport::S7Poll)
+pub const S7_DEFAULT_SLOT: u16 = 1; // S7-1200/1500, S7-300 CPUs are in slot 2
+pub const POLL_INTERVAL_MS: u64 = 1000;
+pub const MIN_POLL_INTERVAL_MS: u64 = 100;
+
+// PLC clock checks over S7, off unless the server sets clock_check_secs
+pub const MIN_CLOCK_CHECK_SECS: u64 = 10;
+pub const DEFAULT_MAX_CLOCK_DRIFT_MS: u64 = 2000;
+
+// Bytes read from the start of the downtime DB named in the configuration packet, see DowntimeSnapshot
+pub const DOWNTIME_DB_SIZE: usize = 20;
+// Time between the two reads of the downtime DB, so buffered downtime events can arrive first
+pub const DOWNTIME_FILL_IN_DELAY_SECS: u64 = 10;
+
+// Default delays between attempts to bind a server's listening socket
+pub const BIND_RETRY_MIN_SECS: u64 = 1;
+pub const BIND_RETRY_MAX_SECS: u64 = 60;
+
+// Silence before a PLC counts as not alive, unless the server sets its own
+pub const KEEPALIVE_TIMEOUT_SECS: u64 = 30;
+
+// Connections one server can track at once, e.g. both CPUs of a redundant pair
+pub const MAX_PEERS: usize = 4;
+
+// How often config.xml is checked for changes made while running
+pub const CONFIG_POLL_MS: u64 = 1000;
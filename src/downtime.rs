@@ -1,8 +1,7 @@
-use crate::constants::EVENT_TYPE_SPECIAL;
-use crate::sql::{connect_to_db, query_packets};
+use crate::constants::{DEFAULT_SECONDS_BEFORE_DOWNTIME, EVENT_TYPE_SPECIAL};
+use crate::sql::{connect_to_db, latest_plc_config, query_packets};
 use crate::event_data::SqlDataPacket;
 use chrono::{Local, DateTime, Datelike};
-use winapi::um::winuser::ENDSESSION_CRITICAL;
 
 #[derive(Clone)]
 pub struct DowntimeRecord {
@@ -11,21 +10,23 @@ pub struct DowntimeRecord {
     pub duration: i64,
 }
 
-// Pairs downtime start (41) and end (42) events and calculates duration
-pub fn process_downtime_packets(packets: Vec<SqlDataPacket>) -> Vec<DowntimeRecord> {
+// Pairs downtime start (41) and end (42) events and calculates duration.
+// `seconds_before_downtime` is added to each record, because the PLC only reports
+// downtime once the machine has already been idle for that long.
+pub fn process_downtime_packets(packets: Vec<SqlDataPacket>, seconds_before_downtime: i64) -> Vec<DowntimeRecord> {
     let mut downtime_records = Vec::new();
     let mut i = 0;
     
     while i < packets.len() {
         // Look for a start event (41)
         if packets[i].packet.plc_packet_code == 41 {
-            let start_time = packets[i].timestamp.clone();
+            let start_time = packets[i].event_time().to_string();
             
             // Look for the next end event (42)
             let mut end_time = None;
             for j in (i + 1)..packets.len() {
                 if packets[j].packet.plc_packet_code == 42 {
-                    end_time = Some(packets[j].timestamp.clone());
+                    end_time = Some(packets[j].event_time().to_string());
                     i = j; // Move to the end event
                     break;
                 }
@@ -33,7 +34,7 @@ pub fn process_downtime_packets(packets: Vec<SqlDataPacket>) -> Vec<DowntimeReco
             
             // If we found a matching end, calculate duration
             if let Some(end) = end_time {
-                let duration = calculate_duration(&start_time, &end);
+                let duration = calculate_duration(&start_time, &end, seconds_before_downtime);
                 downtime_records.push(DowntimeRecord {
                     start: start_time,
                     end,
@@ -48,15 +49,14 @@ pub fn process_downtime_packets(packets: Vec<SqlDataPacket>) -> Vec<DowntimeReco
 }
 
 // Calculates the duration between two RFC3339 timestamps (returns seconds)
-fn calculate_duration(start: &str, end: &str) -> i64 {
+fn calculate_duration(start: &str, end: &str, seconds_before_downtime: i64) -> i64 {
     match (
         DateTime::parse_from_rfc3339(start),
         DateTime::parse_from_rfc3339(end),
     ) {
         (Ok(start_dt), Ok(end_dt)) => {
             let duration = end_dt.signed_duration_since(start_dt);
-            duration.num_seconds() + 120 // There is always 2 minutes of downtime before we capture it.
-            // TODO: Receive a packet from machine centres defining their minimum down time.
+            duration.num_seconds() + seconds_before_downtime // Downtime that elapsed before the PLC reported it.
         }
         _ => 0,
     }
@@ -118,7 +118,10 @@ pub fn downtime_retreive(range: DateRange, sender: &str) -> (String, Result<Vec<
     let start_date = range.start_date();
     let end_date = range.end_date().unwrap_or_else(|| String::new()); // Empty string will create a query that ignores the upper bound
 
-    let conn = connect_to_db().expect("Failed to connect to database");
+    let conn = match connect_to_db() {
+        Ok(conn) => conn,
+        Err(e) => return (String::from("Query: Failed to open the database"), Err(e)),
+    };
     // 41 = downtime start
     // 42 = downtime end
     let sql_result = query_packets(&conn, sender, &start_date, &end_date, &EVENT_TYPE_SPECIAL.to_string(), "41,42");
@@ -133,6 +136,15 @@ pub fn downtime_retreive(range: DateRange, sender: &str) -> (String, Result<Vec<
     (sql_query_str, sql_result)
 }
 
+// The grace period from the PLC's last configuration packet, or the default if it never sent one.
+pub fn seconds_before_downtime(sender: &str) -> i64 {
+    let configured = connect_to_db()
+        .ok()
+        .and_then(|conn| latest_plc_config(&conn, sender).ok().flatten())
+        .map(|c| c.seconds_before_downtime);
+    configured.unwrap_or(DEFAULT_SECONDS_BEFORE_DOWNTIME) as i64
+}
+
 pub fn format_seconds_to_duration(mut seconds: i64) -> String {
     let hours = seconds / 3600;
     seconds %= 3600;
@@ -1,3 +1,7 @@
+use chrono::{DateTime, Local};
+use crate::constants::*;
+use crate::payload_schema::{NamedValue, PayloadSchema, Value, PAYLOAD_SCHEMAS};
+use crate::plc_time::TimestampFormat;
 
 // Event Data Packet Definition
 #[derive(Clone)]
@@ -5,17 +9,185 @@ pub struct EventDataPacket {
     pub raw: Vec<u8>, // Raw bytes of the packet
     pub data_type: u32, // 4 bytes for event type = 1 PLC DINT
     pub plc_packet_code: u32, // 4 bytes for PLC packet type = 1 PLC DINT
+    pub sequence: Option<u32>, // Rolling counter, when the PLC sends one
+    pub plc_timestamp: Option<DateTime<Local>>, // When the PLC says the event happened
     pub data: Vec<u32>,  // Variable length data
+    pub values: Vec<NamedValue>, // Typed values decoded with the payload schema, if there is one
+}
+
+impl EventDataPacket {
+    // An event generated by this application rather than received from the PLC.
+    pub fn internal(plc_packet_code: u32, data: Vec<u32>) -> Self {
+        EventDataPacket {
+            raw: vec![],
+            data_type: EVENT_TYPE_SPECIAL,
+            plc_packet_code,
+            sequence: None,
+            plc_timestamp: None,
+            data,
+            values: vec![],
+        }
+    }
+}
+
+// Optional header fields that follow the event code and packet code.
+// Which ones are present is set per server in config.xml.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PacketLayout {
+    pub sequence: bool, // 1 DINT sequence counter
+    pub timestamp: TimestampFormat, // After the sequence counter
+}
+
+impl PacketLayout {
+    pub fn header_size(&self) -> usize {
+        let mut size = 8;
+        if self.sequence { size += 4; }
+        size + self.timestamp.size()
+    }
 }
 
 #[derive(Clone)]
 pub struct SqlDataPacket {
+    pub id: i64, // Row in event_data, which snapshots refer to
     pub query: String,
     pub plc: String,
-    pub timestamp: String,
+    pub timestamp: String, // When the packet was received
+    pub plc_timestamp: Option<String>, // When the PLC says it happened
     pub packet: EventDataPacket,
 }
 
+impl SqlDataPacket {
+    // The PLC's timestamp when it sent one, otherwise the time the packet was received.
+    pub fn event_time(&self) -> &str {
+        self.plc_timestamp.as_deref().unwrap_or(&self.timestamp)
+    }
+}
+
+// DB range read over S7 because an event matched a snapshot trigger
+#[derive(Clone, Debug)]
+pub struct EventSnapshot {
+    pub event_id: i64, // Row in event_data of the event that triggered it
+    pub timestamp: String, // When it was read
+    pub db: u16,
+    pub start: u16,
+    pub data: Vec<u8>,
+    pub values: Vec<NamedValue>, // Decoded with the area's schema, if it has one
+}
+
+// Runtime settings announced by the PLC in the configuration packet (code 22)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlcRuntimeConfig {
+    pub downtime_db: u32, // DB number that contains the downtime info
+    pub seconds_before_downtime: u32, // Time without production before the PLC counts it as downtime
+}
+
+pub fn parse_config_packet(packet: &EventDataPacket) -> Option<PlcRuntimeConfig> {
+    if packet.data.len() < 2 {
+        return None;
+    }
+    Some(PlcRuntimeConfig {
+        downtime_db: packet.data[0],
+        seconds_before_downtime: packet.data[1],
+    })
+}
+
+// The start of the downtime DB named in the configuration packet, read over S7.
+// Five DInts: InDowntime (0 or 1), ReasonCode, DowntimeCount, TotalDowntimeSeconds, CurrentDowntimeSeconds,
+// unless the server names a payload schema with fields called that.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DowntimeSnapshot {
+    pub in_downtime: bool,
+    pub reason_code: u32, // Of the current downtime, or the last one
+    pub downtime_count: u32,
+    pub total_downtime_secs: u32,
+    pub current_downtime_secs: u32, // How long the current downtime has lasted, 0 while running
+}
+
+impl DowntimeSnapshot {
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < DOWNTIME_DB_SIZE {
+            return None;
+        }
+        let dint = |i: usize| u32::from_be_bytes([bytes[i * 4], bytes[i * 4 + 1], bytes[i * 4 + 2], bytes[i * 4 + 3]]);
+        Some(DowntimeSnapshot {
+            in_downtime: dint(0) != 0,
+            reason_code: dint(1),
+            downtime_count: dint(2),
+            total_downtime_secs: dint(3),
+            current_downtime_secs: dint(4),
+        })
+    }
+
+    // The DB decoded with the server's downtime schema, or as the 5 DInts without one
+    pub fn decode(bytes: &[u8], schema: Option<&PayloadSchema>) -> Option<Self> {
+        match schema {
+            Some(schema) => Self::from_values(&schema.decode(bytes)),
+            None => Self::parse(bytes),
+        }
+    }
+
+    // None if one of DOWNTIME_FIELDS is missing or isn't a whole number from 0 up, which
+    // means the schema doesn't describe the DB
+    pub fn from_values(values: &[NamedValue]) -> Option<Self> {
+        let number = |name: &str| match &values.iter().find(|v| v.name == name)?.value {
+            Value::Bool(b) => Some(*b as u32),
+            Value::Number(n) if n.fract() == 0.0 && (0.0..=u32::MAX as f64).contains(n) => Some(*n as u32),
+            _ => None,
+        };
+        Some(DowntimeSnapshot {
+            in_downtime: number("InDowntime")? != 0,
+            reason_code: number("ReasonCode")?,
+            downtime_count: number("DowntimeCount")?,
+            total_downtime_secs: number("TotalDowntimeSeconds")?,
+            current_downtime_secs: number("CurrentDowntimeSeconds")?,
+        })
+    }
+
+    pub fn check_schema(schema: &PayloadSchema) -> Result<(), String> {
+        match DOWNTIME_FIELDS.iter().find(|&&f| !schema.fields.iter().any(|field| field.name == f)) {
+            Some(missing) => Err(format!("downtime schema '{}' has no {} field", schema.name, missing)),
+            None => Ok(()),
+        }
+    }
+
+    // True if a later read agrees on the state and the number of downtimes, so no downtime
+    // start or end happened between the two.
+    pub fn settled(&self, later: &DowntimeSnapshot) -> bool {
+        self.in_downtime == later.in_downtime && self.downtime_count == later.downtime_count
+    }
+
+    // The downtime start or end that the PLC's state says is missing, given the code of the last
+    // one stored. A filled in start is dated from CurrentDowntimeSeconds, a filled in end from `now`.
+    pub fn missing_event(&self, last_stored: Option<u32>, now: DateTime<Local>) -> Option<EventDataPacket> {
+        let code = match (self.in_downtime, last_stored) {
+            (true, Some(PACKET_CODE_DOWNTIME_START)) => return None,
+            (true, _) => PACKET_CODE_DOWNTIME_START,
+            (false, Some(PACKET_CODE_DOWNTIME_START)) => PACKET_CODE_DOWNTIME_END,
+            (false, _) => return None,
+        };
+        let mut event = EventDataPacket::internal(code, vec![self.reason_code]);
+        event.values = vec![NamedValue { name: "filled_in".to_string(), value: Value::Bool(true), unit: String::new() }];
+        if code == PACKET_CODE_DOWNTIME_START {
+            event.plc_timestamp = Some(now - chrono::Duration::seconds(self.current_downtime_secs as i64));
+        }
+        Some(event)
+    }
+}
+
+// What a payload schema for the downtime DB has to name its fields
+pub const DOWNTIME_FIELDS: [&str; 5] = ["InDowntime", "ReasonCode", "DowntimeCount", "TotalDowntimeSeconds", "CurrentDowntimeSeconds"];
+
+// The schema named in a server's downtime_schema, None for the 5 DInts
+pub fn downtime_schema(name: &str) -> Result<Option<&'static PayloadSchema>, String> {
+    if name.is_empty() {
+        return Ok(None);
+    }
+    let schema = PAYLOAD_SCHEMAS.find_by_name(name)
+        .ok_or_else(|| format!("downtime schema '{}' isn't in the schema file", name))?;
+    DowntimeSnapshot::check_schema(schema)?;
+    Ok(Some(schema))
+}
+
 pub fn is_keepalive_packet(packet: &EventDataPacket) -> bool {
     // Check if the packet is a system packet based on event_code
     // 12 = keep alive packet
@@ -23,25 +195,45 @@ pub fn is_keepalive_packet(packet: &EventDataPacket) -> bool {
     // 50 = plc event packet
 }
 
-pub fn parse_event_data_packet(bytes: &[u8]) -> Option<EventDataPacket> {
-    if bytes.len() < 8 || bytes.len() % 4 != 0 {
-        return None; // Not enough data or misaligned
+pub fn parse_event_data_packet(bytes: &[u8], layout: &PacketLayout) -> Option<EventDataPacket> {
+    let header_size = layout.header_size();
+    if bytes.len() < header_size {
+        return None; // Not enough data
     }
 
     let raw = bytes.to_vec(); // Store the raw bytes
     let data_type = u32::from_be_bytes(bytes[0..4].try_into().ok()?);
     let plc_packet_code = u32::from_be_bytes(bytes[4..8].try_into().ok()?);
+    let sequence = if layout.sequence {
+        Some(u32::from_be_bytes(bytes[8..12].try_into().ok()?))
+    } else {
+        None
+    };
+    let timestamp_start = if layout.sequence { 12 } else { 8 };
+    let plc_timestamp = layout.timestamp.decode(&bytes[timestamp_start..header_size]);
+
+    // Without a schema everything must be DINTs. A schema can describe INTs, BYTEs
+    // and STRINGs, so the payload doesn't have to be a multiple of 4 bytes.
+    let schema = PAYLOAD_SCHEMAS.find(data_type, plc_packet_code);
+    if schema.is_none() && bytes.len() % 4 != 0 {
+        return None; // Misaligned
+    }
 
     let mut data = Vec::new();
-    for chunk in bytes[8..].chunks(4) {
+    for chunk in bytes[header_size..].chunks_exact(4) {
         let value = u32::from_be_bytes(chunk.try_into().ok()?);
         data.push(value);
     }
 
+    let values = schema.map(|s| s.decode(&bytes[header_size..])).unwrap_or_default();
+
     Some(EventDataPacket {
         raw,
         data_type,
         plc_packet_code,
+        sequence,
+        plc_timestamp,
         data,
+        values,
     })
-}
\ No newline at end of file
+}
@@ -0,0 +1,85 @@
+use serde::{Serialize, Deserialize};
+
+pub const MAX_PACKET_SIZE: usize = 512; // Largest packet the PLC is allowed to send
+pub const LENGTH_HEADER_SIZE: usize = 4; // 1 PLC DINT
+
+// How packets are delimited on the TCP byte stream.
+// TCP does not preserve TSEND boundaries, so two sends can arrive in one read,
+// or one send can be split across several reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FramingMode {
+    #[default]
+    Raw,            // Every read is treated as one packet (legacy behaviour)
+    LengthPrefixed, // Each packet is preceded by a 4 byte big-endian length (DINT)
+    FixedRecord,    // Every packet is exactly `record_length` bytes long
+}
+
+// Reassembles complete packets from the bytes received on a connection.
+pub struct FrameDecoder {
+    mode: FramingMode,
+    record_length: usize,
+    buffer: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new(mode: FramingMode, record_length: usize) -> Self {
+        Self {
+            mode,
+            record_length,
+            buffer: Vec::with_capacity(MAX_PACKET_SIZE * 2),
+        }
+    }
+
+    // Add freshly received bytes to the end of the buffer.
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    // Number of bytes waiting for the rest of their frame.
+    pub fn pending(&self) -> usize {
+        self.buffer.len()
+    }
+
+    // Take the next complete frame out of the buffer.
+    // Returns Ok(None) when more bytes are needed. An error means the stream can't
+    // be resynchronised, and the connection should be closed.
+    pub fn next_frame(&mut self) -> Result<Option<Vec<u8>>, &'static str> {
+        match self.mode {
+            FramingMode::Raw => {
+                if self.buffer.is_empty() {
+                    Ok(None)
+                } else {
+                    Ok(Some(std::mem::take(&mut self.buffer)))
+                }
+            }
+            FramingMode::LengthPrefixed => {
+                if self.buffer.len() < LENGTH_HEADER_SIZE {
+                    return Ok(None);
+                }
+                let length = u32::from_be_bytes([self.buffer[0], self.buffer[1], self.buffer[2], self.buffer[3]]) as usize;
+                if length == 0 || length > MAX_PACKET_SIZE {
+                    self.buffer.clear();
+                    return Err("Invalid frame length in header");
+                }
+                if self.buffer.len() < LENGTH_HEADER_SIZE + length {
+                    return Ok(None);
+                }
+                let frame = self.buffer[LENGTH_HEADER_SIZE..LENGTH_HEADER_SIZE + length].to_vec();
+                self.buffer.drain(..LENGTH_HEADER_SIZE + length);
+                Ok(Some(frame))
+            }
+            FramingMode::FixedRecord => {
+                if self.record_length == 0 || self.record_length > MAX_PACKET_SIZE {
+                    self.buffer.clear();
+                    return Err("Invalid record length in server config");
+                }
+                if self.buffer.len() < self.record_length {
+                    return Ok(None);
+                }
+                let frame: Vec<u8> = self.buffer.drain(..self.record_length).collect();
+                Ok(Some(frame))
+            }
+        }
+    }
+}
@@ -0,0 +1,230 @@
+// RFC 1006 (ISO transport over TCP), used by TCON connections of type 0x12 (ISO-on-TCP).
+// Every TPKT carries one COTP TPDU. Only class 0 is needed: connection request/confirm,
+// data and disconnect. A TSEND arrives as one or more data TPDUs, the last one flagged EOT.
+use crate::framing::MAX_PACKET_SIZE;
+
+const TPKT_VERSION: u8 = 3;
+const TPKT_HEADER_SIZE: usize = 4;
+
+// COTP TPDU codes
+const COTP_CR: u8 = 0xE0; // Connection request
+const COTP_CC: u8 = 0xD0; // Connection confirm
+const COTP_DR: u8 = 0x80; // Disconnect request
+const COTP_DT: u8 = 0xF0; // Data
+const COTP_EOT: u8 = 0x80; // Set on the last data TPDU of a TSEND
+
+const CR_HEADER_SIZE: usize = 7; // Including the length indicator, not including parameters
+const DT_HEADER_SIZE: usize = 3;
+
+// Connection request/confirm parameters
+const PARAM_TPDU_SIZE: u8 = 0xC0;
+const PARAM_CALLING_TSAP: u8 = 0xC1;
+const PARAM_CALLED_TSAP: u8 = 0xC2;
+const TPDU_SIZE_1024: u8 = 0x0A; // 2^10 bytes
+
+const LOCAL_REFERENCE: u16 = 1;
+
+// TSAPs are written as hex bytes, with optional '.', ':' or ' ' between them, e.g. "E0.01" or "4D4F4E".
+// An empty string gives an empty TSAP.
+pub fn parse_tsap(tsap: &str) -> Result<Vec<u8>, &'static str> {
+    let digits: Vec<char> = tsap.chars().filter(|c| !matches!(c, '.' | ':' | ' ')).collect();
+    if digits.len() % 2 != 0 {
+        return Err("TSAP must be a whole number of hex bytes");
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let byte: String = pair.iter().collect();
+            u8::from_str_radix(&byte, 16).map_err(|_| "TSAP contains a character that isn't hex")
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IsoFrame {
+    Reply(Vec<u8>), // Send back as is, e.g. the connection confirm
+    Data(Vec<u8>), // One complete TSEND
+    Disconnect, // The partner closed the ISO connection
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    AwaitingRequest, // Passive, waiting for the PLC's CR
+    AwaitingConfirm, // Active, sent our CR
+    Connected,
+}
+
+pub struct IsoSession {
+    state: State,
+    local_tsap: Vec<u8>, // Empty accepts any called TSAP
+    remote_tsap: Vec<u8>, // Empty accepts any calling TSAP
+    buffer: Vec<u8>, // Bytes not yet making a full TPKT
+    tsdu: Vec<u8>, // Data TPDUs waiting for the one flagged EOT
+}
+
+impl IsoSession {
+    // The PLC opens the connection.
+    pub fn listen(local_tsap: Vec<u8>, remote_tsap: Vec<u8>) -> Self {
+        IsoSession {
+            state: State::AwaitingRequest,
+            local_tsap,
+            remote_tsap,
+            buffer: Vec::new(),
+            tsdu: Vec::new(),
+        }
+    }
+
+    // We open the connection. Returns the connection request to send first.
+    pub fn connect(local_tsap: Vec<u8>, remote_tsap: Vec<u8>) -> (Self, Vec<u8>) {
+        let mut cotp = vec![0, COTP_CR, 0, 0];
+        cotp.extend_from_slice(&LOCAL_REFERENCE.to_be_bytes());
+        cotp.push(0); // Class 0
+        cotp.extend_from_slice(&[PARAM_TPDU_SIZE, 1, TPDU_SIZE_1024]);
+        push_param(&mut cotp, PARAM_CALLING_TSAP, &local_tsap);
+        push_param(&mut cotp, PARAM_CALLED_TSAP, &remote_tsap);
+        cotp[0] = (cotp.len() - 1) as u8;
+        let request = tpkt(&cotp);
+        let session = IsoSession {
+            state: State::AwaitingConfirm,
+            ..Self::listen(local_tsap, remote_tsap)
+        };
+        (session, request)
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    // Bytes received that aren't part of a complete TSEND yet
+    pub fn pending(&self) -> usize {
+        self.buffer.len() + self.tsdu.len()
+    }
+
+    // An error means the stream can't be trusted any more, and the connection should be closed.
+    pub fn next_frame(&mut self) -> Result<Option<IsoFrame>, &'static str> {
+        while let Some(tpdu) = self.next_tpdu()? {
+            if let Some(frame) = self.handle_tpdu(&tpdu)? {
+                return Ok(Some(frame));
+            }
+        }
+        Ok(None)
+    }
+
+    fn next_tpdu(&mut self) -> Result<Option<Vec<u8>>, &'static str> {
+        if self.buffer.len() < TPKT_HEADER_SIZE {
+            return Ok(None);
+        }
+        if self.buffer[0] != TPKT_VERSION {
+            return Err("Not a TPKT header (version isn't 3)");
+        }
+        let length = u16::from_be_bytes([self.buffer[2], self.buffer[3]]) as usize;
+        if length < TPKT_HEADER_SIZE + 2 {
+            return Err("TPKT length is too short for a COTP header");
+        }
+        if self.buffer.len() < length {
+            return Ok(None); // Wait for the rest
+        }
+        let tpdu = self.buffer[TPKT_HEADER_SIZE..length].to_vec();
+        self.buffer.drain(..length);
+        Ok(Some(tpdu))
+    }
+
+    fn handle_tpdu(&mut self, tpdu: &[u8]) -> Result<Option<IsoFrame>, &'static str> {
+        let header_size = tpdu[0] as usize + 1; // The length indicator doesn't count itself
+        if header_size < 2 || header_size > tpdu.len() {
+            return Err("COTP header length doesn't fit the TPKT");
+        }
+        let header = &tpdu[..header_size];
+        match (self.state, header[1] & 0xF0) {
+            (State::AwaitingRequest, COTP_CR) => {
+                let confirm = self.accept(header)?;
+                self.state = State::Connected;
+                Ok(Some(IsoFrame::Reply(confirm)))
+            }
+            (State::AwaitingConfirm, COTP_CC) => {
+                self.state = State::Connected;
+                Ok(None)
+            }
+            (State::Connected, COTP_DT) => {
+                if header_size < DT_HEADER_SIZE {
+                    return Err("COTP data header is too short");
+                }
+                self.tsdu.extend_from_slice(&tpdu[header_size..]);
+                if self.tsdu.len() > MAX_PACKET_SIZE {
+                    return Err("ISO-on-TCP packet is larger than 512 bytes");
+                }
+                if header[2] & COTP_EOT != 0 {
+                    Ok(Some(IsoFrame::Data(std::mem::take(&mut self.tsdu))))
+                } else {
+                    Ok(None) // More to come
+                }
+            }
+            (_, COTP_DR) => Ok(Some(IsoFrame::Disconnect)),
+            (State::Connected, _) => Err("Unexpected COTP TPDU on an open connection"),
+            _ => Err("Unexpected COTP TPDU before the connection was set up"),
+        }
+    }
+
+    // Checks the PLC's connection request and builds our confirm.
+    fn accept(&self, header: &[u8]) -> Result<Vec<u8>, &'static str> {
+        if header.len() < CR_HEADER_SIZE {
+            return Err("COTP connection request is too short");
+        }
+        let remote_reference = [header[4], header[5]];
+        let mut tpdu_size = None;
+        let mut calling_tsap: &[u8] = &[];
+        let mut called_tsap: &[u8] = &[];
+        let mut params = &header[CR_HEADER_SIZE..];
+        while params.len() >= 2 {
+            let (code, length) = (params[0], params[1] as usize);
+            let value = params.get(2..2 + length).ok_or("COTP parameter runs past the header")?;
+            match code {
+                PARAM_TPDU_SIZE => tpdu_size = value.first().copied(),
+                PARAM_CALLING_TSAP => calling_tsap = value,
+                PARAM_CALLED_TSAP => called_tsap = value,
+                _ => {} // Not used by class 0
+            }
+            params = &params[2 + length..];
+        }
+        if !self.local_tsap.is_empty() && called_tsap != self.local_tsap.as_slice() {
+            return Err("Called TSAP doesn't match local_tsap");
+        }
+        if !self.remote_tsap.is_empty() && calling_tsap != self.remote_tsap.as_slice() {
+            return Err("Calling TSAP doesn't match remote_tsap");
+        }
+
+        let mut cotp = vec![0, COTP_CC, remote_reference[0], remote_reference[1]];
+        cotp.extend_from_slice(&LOCAL_REFERENCE.to_be_bytes());
+        cotp.push(0); // Class 0
+        if let Some(size) = tpdu_size {
+            cotp.extend_from_slice(&[PARAM_TPDU_SIZE, 1, size.min(TPDU_SIZE_1024)]);
+        }
+        push_param(&mut cotp, PARAM_CALLING_TSAP, calling_tsap);
+        push_param(&mut cotp, PARAM_CALLED_TSAP, called_tsap);
+        cotp[0] = (cotp.len() - 1) as u8;
+        Ok(tpkt(&cotp))
+    }
+}
+
+// Wraps a response in a single data TPDU.
+pub fn wrap_data(data: &[u8]) -> Vec<u8> {
+    let mut cotp = vec![(DT_HEADER_SIZE - 1) as u8, COTP_DT, COTP_EOT];
+    cotp.extend_from_slice(data);
+    tpkt(&cotp)
+}
+
+fn tpkt(cotp: &[u8]) -> Vec<u8> {
+    let length = (TPKT_HEADER_SIZE + cotp.len()) as u16;
+    let mut bytes = vec![TPKT_VERSION, 0];
+    bytes.extend_from_slice(&length.to_be_bytes());
+    bytes.extend_from_slice(cotp);
+    bytes
+}
+
+fn push_param(cotp: &mut Vec<u8>, code: u8, value: &[u8]) {
+    if !value.is_empty() {
+        cotp.push(code);
+        cotp.push(value.len() as u8);
+        cotp.extend_from_slice(value);
+    }
+}
@@ -0,0 +1,25 @@
+// Everything except the entry points, shared by the windowed app (main.rs) and the
+// headless service (bin/headless.rs).
+pub mod app_config;
+pub mod clock_drift;
+pub mod comms_monitor;
+pub mod comms_s7;
+pub mod comms_tcon;
+pub mod config_store;
+pub mod constants;
+pub mod downtime;
+pub mod sql;
+pub mod event_data;
+pub mod filehandling;
+pub mod framing;
+pub mod iso_on_tcp;
+pub mod payload_schema;
+pub mod plc_time;
+pub mod registryhandling;
+pub mod response;
+pub mod sequence;
+pub mod server_id;
+pub mod simulator;
+pub mod utils;
+pub mod ui;
+pub mod xmlhandling;
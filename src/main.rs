@@ -1,23 +1,14 @@
 #![windows_subsystem = "windows"]
 
-mod app_config;
-mod comms_tcon;
-mod constants;
-mod downtime;
-mod sql;
-mod event_data;
-mod filehandling;
-mod registryhandling;
-mod utils;
-mod ui;
-mod xmlhandling;
-
 use floem::prelude::*;
 use std::sync::{Arc, mpsc};
 use tokio::sync::Notify;
-use crate::comms_tcon::*;
-use crate::ui::*;
-use crate::utils::log;
+use s7_event_monitor::app_config;
+use s7_event_monitor::comms_tcon::*;
+use s7_event_monitor::config_store::{config_path, SERVER_CONFIG};
+use s7_event_monitor::ui::*;
+use s7_event_monitor::utils::{debug_logging, log};
+use s7_event_monitor::xmlhandling;
 
 pub const OS_MOD: Modifiers = if cfg!(target_os = "macos") {
     Modifiers::META
@@ -33,39 +24,36 @@ async fn main() {
     let status_tx_clone = status_tx.clone();
     // Create server manager using the proper constructor
     let (mut server_manager, command_tx) = ServerManager::new(status_tx, shutdown_notify.clone());
+    // Initial statuses include anything the manager loaded from the database
+    let initial_statuses = server_manager.server_status.server.clone();
         
     // Spawn background task to handle server commands
     let _manager_handle = tokio::spawn(async move {
         server_manager.process_commands().await;
     });
 
+    // Pick up edits to config.xml without a restart
+    let _config_watcher = tokio::spawn(xmlhandling::watch_config(config_path(), command_tx.clone()));
+
     // Start autostart servers if needed
-    unsafe {
-        for (idx, server) in SERVER_CONFIG.server.iter_mut().enumerate() {
-            println!("Server ID: {:?}", server.id);
-            // Start server if autostart is enabled
-            if server.autostart {
-                log(&format!("Auto-starting server {}: {}:{}",
-                         idx,
-                         server.ip_address,
-                         server.port));
-                // Send start command through the channel
-                let _ = command_tx.send(ServerCommand::Start(idx));
-            }
-            // Send initial status for this server
-            let initial_status = ServerStatusInfo {
-                idx,
-                server_id: server.id.clone(),
-                is_running: false,
-                is_connected: false,
-                is_alive: false,
-                peer_ip: [0; 16],
-                last_packet_time: 0,
-                new_data: false,
-            };
-            println!("Sending initial status for server {}: {:?}", idx, initial_status);
-            let _ = status_tx_clone.send(initial_status);
+    for server in SERVER_CONFIG.servers().iter() {
+        if debug_logging() { log(&format!("Server ID: {}", server.id)); }
+        // Start server if autostart is enabled
+        if server.autostart {
+            log(&format!("Auto-starting server {}: {}:{}",
+                     server.name,
+                     server.ip_address,
+                     server.port));
+            // Send start command through the channel
+            let _ = command_tx.send(ServerCommand::Start(server.id));
         }
+        // Send initial status for this server
+        let initial_status = initial_statuses.iter()
+            .find(|s| s.matches_server_id(&server.id))
+            .copied()
+            .unwrap_or_else(|| ServerStatusInfo::new(server.id));
+        if debug_logging() { log(&format!("Sending initial status for server {}: {:?}", server.name, initial_status)); }
+        let _ = status_tx_clone.send(initial_status);
     }
     
     // Floem UI has to run on the main thread
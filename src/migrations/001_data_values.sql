@@ -0,0 +1,2 @@
+-- Typed values decoded with the payload schema, stored as JSON.
+ALTER TABLE event_data ADD COLUMN data_values TEXT;
@@ -0,0 +1,9 @@
+-- Runtime configuration received from each PLC in configuration packets (code 22).
+CREATE TABLE plc_config (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    plc TEXT NOT NULL,
+    timestamp TEXT NOT NULL,
+    downtime_db INTEGER NOT NULL,
+    seconds_before_downtime INTEGER NOT NULL
+);
+CREATE INDEX idx_plc_config_plc ON plc_config (plc, id);
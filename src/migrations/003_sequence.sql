@@ -0,0 +1,2 @@
+-- Rolling sequence counter sent by PLCs that have sequence numbers enabled.
+ALTER TABLE event_data ADD COLUMN sequence INTEGER;
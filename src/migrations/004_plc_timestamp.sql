@@ -0,0 +1,2 @@
+-- Time the PLC says the event happened. `timestamp` stays as the time it was received.
+ALTER TABLE event_data ADD COLUMN plc_timestamp TEXT;
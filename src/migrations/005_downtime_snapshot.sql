@@ -0,0 +1,14 @@
+-- Start of the downtime DB, read over S7 after a configuration packet (code 22).
+CREATE TABLE downtime_snapshot (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    plc TEXT NOT NULL,
+    timestamp TEXT NOT NULL,
+    downtime_db INTEGER NOT NULL,
+    in_downtime INTEGER NOT NULL,
+    reason_code INTEGER NOT NULL,
+    downtime_count INTEGER NOT NULL,
+    total_downtime_secs INTEGER NOT NULL,
+    current_downtime_secs INTEGER NOT NULL,
+    filled_in INTEGER -- 41 or 42 if a missing downtime start or end was stored because of this snapshot
+);
+CREATE INDEX idx_downtime_snapshot_plc ON downtime_snapshot (plc, id);
@@ -0,0 +1,12 @@
+-- DB ranges read over S7 when an event matched a snapshot trigger, linked to that event.
+CREATE TABLE event_snapshot (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    event_id INTEGER NOT NULL REFERENCES event_data (id),
+    plc TEXT NOT NULL,
+    timestamp TEXT NOT NULL,
+    db INTEGER NOT NULL,
+    start INTEGER NOT NULL,
+    data BLOB NOT NULL,
+    data_values TEXT
+);
+CREATE INDEX idx_event_snapshot_event ON event_snapshot (event_id);
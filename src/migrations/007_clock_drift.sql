@@ -0,0 +1,9 @@
+-- PLC clock read over S7 every clock_check_secs, compared with the PC's clock.
+CREATE TABLE clock_drift (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    plc TEXT NOT NULL,
+    timestamp TEXT NOT NULL, -- PC time of the read
+    plc_time TEXT NOT NULL, -- As the PLC had it, local time without an offset
+    drift_ms INTEGER NOT NULL -- PLC time minus PC time
+);
+CREATE INDEX idx_clock_drift_plc ON clock_drift (plc, id);
@@ -0,0 +1,13 @@
+-- Every write made to a PLC from the Connections tab, including the ones that failed.
+CREATE TABLE write_audit (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    plc TEXT NOT NULL,
+    timestamp TEXT NOT NULL,
+    action TEXT NOT NULL, -- The WriteAction's name
+    address TEXT NOT NULL, -- e.g. DB30.DBW4
+    value TEXT NOT NULL, -- As entered
+    data BLOB, -- Bytes written, NULL if nothing was
+    user TEXT NOT NULL, -- Windows user running the application
+    error TEXT -- NULL if the write succeeded
+);
+CREATE INDEX idx_write_audit_plc ON write_audit (plc, id);
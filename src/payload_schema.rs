@@ -0,0 +1,186 @@
+use serde::{Serialize, Deserialize};
+use once_cell::sync::Lazy;
+use crate::utils::{log, log_warn};
+use crate::xmlhandling::load_schemas;
+
+pub const SCHEMA_FILE: &str = "schemas.xml";
+
+// Data types the PLC can put into a packet. Names match TIA Portal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum PlcType {
+    Bool,   // 1 bit, `bit` selects which bit of the byte
+    Byte,   // 1 byte unsigned
+    Int,    // 2 bytes signed
+    Uint,   // 2 bytes unsigned
+    Dint,   // 4 bytes signed
+    Udint,  // 4 bytes unsigned
+    Real,   // 4 bytes IEEE 754
+    String, // S7 STRING: max length byte, actual length byte, then characters
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+// One named value inside the packet payload (after the 8 header bytes).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FieldSchema {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub plc_type: PlcType,
+    pub byte: usize, // Byte offset from the start of the payload
+    #[serde(default)]
+    pub bit: u8, // Only used for BOOL
+    #[serde(default = "default_scale")]
+    pub scale: f64, // Numeric values are multiplied by this
+    #[serde(default)]
+    pub unit: String,
+}
+
+impl FieldSchema {
+    // Bytes from the start of the payload to the end of the field. A STRING is counted at its longest.
+    pub fn end(&self) -> usize {
+        self.byte + match self.plc_type {
+            PlcType::Bool | PlcType::Byte => 1,
+            PlcType::Int | PlcType::Uint => 2,
+            PlcType::Dint | PlcType::Udint | PlcType::Real => 4,
+            PlcType::String => 256,
+        }
+    }
+}
+
+// Describes the payload for one data_type / plc_packet_code combination.
+// If plc_packet_code is missing the schema applies to every code of that data_type.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PayloadSchema {
+    pub name: String,
+    pub data_type: u32,
+    #[serde(default)]
+    pub plc_packet_code: Option<u32>,
+    #[serde(rename = "Field", default)]
+    pub fields: Vec<FieldSchema>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SchemaRegistry {
+    #[serde(rename = "Schema", default)]
+    pub schema: Vec<PayloadSchema>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Value {
+    Bool(bool),
+    Number(f64),
+    Text(String),
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Value::Bool(b) => write!(f, "{}", if *b { "TRUE" } else { "FALSE" }),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Text(s) => write!(f, "'{}'", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NamedValue {
+    pub name: String,
+    pub value: Value,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub unit: String,
+}
+
+impl std::fmt::Display for NamedValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.unit.is_empty() {
+            write!(f, "{}={}", self.name, self.value)
+        } else {
+            write!(f, "{}={} {}", self.name, self.value, self.unit)
+        }
+    }
+}
+
+pub static PAYLOAD_SCHEMAS: Lazy<SchemaRegistry> = Lazy::new(|| {
+    match load_schemas(SCHEMA_FILE) {
+        Ok(registry) => {
+            log(&format!("Loaded {} payload schemas from {}", registry.schema.len(), SCHEMA_FILE));
+            registry
+        }
+        Err(e) => {
+            log(&format!("No payload schemas loaded from {}: {}", SCHEMA_FILE, e));
+            SchemaRegistry::default()
+        }
+    }
+});
+
+impl SchemaRegistry {
+    // An exact plc_packet_code match wins over a schema that covers the whole data_type.
+    pub fn find(&self, data_type: u32, plc_packet_code: u32) -> Option<&PayloadSchema> {
+        self.schema.iter()
+            .find(|s| s.data_type == data_type && s.plc_packet_code == Some(plc_packet_code))
+            .or_else(|| self.schema.iter().find(|s| s.data_type == data_type && s.plc_packet_code.is_none()))
+    }
+
+    // For data that isn't an event's payload, e.g. a DB snapshot
+    pub fn find_by_name(&self, name: &str) -> Option<&PayloadSchema> {
+        self.schema.iter().find(|s| s.name == name)
+    }
+}
+
+impl PayloadSchema {
+    // Bytes to read so every field can be decoded
+    pub fn size(&self) -> usize {
+        self.fields.iter().map(|f| f.end()).max().unwrap_or(0)
+    }
+
+    // Decode every field that fits inside the payload. Fields past the end are skipped.
+    pub fn decode(&self, payload: &[u8]) -> Vec<NamedValue> {
+        let mut values = Vec::with_capacity(self.fields.len());
+        for field in &self.fields {
+            match decode_field(field, payload) {
+                Some(value) => values.push(NamedValue {
+                    name: field.name.clone(),
+                    value,
+                    unit: field.unit.clone(),
+                }),
+                None => log_warn(&format!("Schema '{}': field '{}' does not fit in {} byte payload",
+                                     self.name, field.name, payload.len())),
+            }
+        }
+        values
+    }
+}
+
+fn decode_field(field: &FieldSchema, payload: &[u8]) -> Option<Value> {
+    let b = field.byte;
+    let number = |n: f64| Some(Value::Number(n * field.scale));
+    match field.plc_type {
+        PlcType::Bool => {
+            if field.bit > 7 {
+                return None;
+            }
+            let byte = *payload.get(b)?;
+            Some(Value::Bool(byte & (1 << field.bit) != 0))
+        }
+        PlcType::Byte => number(*payload.get(b)? as f64),
+        PlcType::Int => number(i16::from_be_bytes(payload.get(b..b + 2)?.try_into().ok()?) as f64),
+        PlcType::Uint => number(u16::from_be_bytes(payload.get(b..b + 2)?.try_into().ok()?) as f64),
+        PlcType::Dint => number(i32::from_be_bytes(payload.get(b..b + 4)?.try_into().ok()?) as f64),
+        PlcType::Udint => number(u32::from_be_bytes(payload.get(b..b + 4)?.try_into().ok()?) as f64),
+        PlcType::Real => number(f32::from_be_bytes(payload.get(b..b + 4)?.try_into().ok()?) as f64),
+        PlcType::String => {
+            let max_len = *payload.get(b)? as usize;
+            let len = (*payload.get(b + 1)? as usize).min(max_len);
+            let chars = payload.get(b + 2..b + 2 + len)?;
+            Some(Value::Text(String::from_utf8_lossy(chars).to_string()))
+        }
+    }
+}
+
+pub fn format_values(values: &[NamedValue]) -> String {
+    values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", ")
+}
@@ -0,0 +1,70 @@
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike};
+use serde::{Serialize, Deserialize};
+
+pub const DTL_SIZE: usize = 12;
+pub const LDT_SIZE: usize = 8;
+
+// Timestamp the PLC puts in each packet, if any.
+// Both are treated as the PLC's local wall-clock time, in the PC's time zone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampFormat {
+    #[default]
+    None,
+    Dtl, // 12 bytes, e.g. from RD_LOC_T
+    Ldt, // 8 bytes, nanoseconds since 1970-01-01
+}
+
+impl TimestampFormat {
+    pub fn size(&self) -> usize {
+        match self {
+            Self::None => 0,
+            Self::Dtl => DTL_SIZE,
+            Self::Ldt => LDT_SIZE,
+        }
+    }
+
+    // Returns None for an invalid date (e.g. an uninitialised DTL of all zeros).
+    pub fn decode(&self, bytes: &[u8]) -> Option<DateTime<Local>> {
+        let naive = match self {
+            Self::None => return None,
+            Self::Dtl => decode_dtl(bytes)?,
+            Self::Ldt => decode_ldt(bytes)?,
+        };
+        Local.from_local_datetime(&naive).earliest()
+    }
+}
+
+// DTL: YEAR (UINT), MONTH, DAY, WEEKDAY, HOUR, MINUTE, SECOND (USINT), NANOSECOND (UDINT)
+pub fn decode_dtl(bytes: &[u8]) -> Option<NaiveDateTime> {
+    let bytes = bytes.get(..DTL_SIZE)?;
+    let year = u16::from_be_bytes([bytes[0], bytes[1]]) as i32;
+    let nanosecond = u32::from_be_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]);
+    NaiveDate::from_ymd_opt(year, bytes[2] as u32, bytes[3] as u32)?
+        .and_hms_nano_opt(bytes[5] as u32, bytes[6] as u32, bytes[7] as u32, nanosecond)
+}
+
+pub fn encode_dtl(time: &NaiveDateTime) -> [u8; DTL_SIZE] {
+    let mut bytes = [0u8; DTL_SIZE];
+    bytes[0..2].copy_from_slice(&(time.year() as u16).to_be_bytes());
+    bytes[2] = time.month() as u8;
+    bytes[3] = time.day() as u8;
+    bytes[4] = time.weekday().number_from_sunday() as u8; // 1 = Sunday
+    bytes[5] = time.hour() as u8;
+    bytes[6] = time.minute() as u8;
+    bytes[7] = time.second() as u8;
+    bytes[8..12].copy_from_slice(&time.nanosecond().to_be_bytes());
+    bytes
+}
+
+pub fn decode_ldt(bytes: &[u8]) -> Option<NaiveDateTime> {
+    let nanos = u64::from_be_bytes(bytes.get(..LDT_SIZE)?.try_into().ok()?);
+    let secs = (nanos / 1_000_000_000) as i64;
+    let nsecs = (nanos % 1_000_000_000) as u32;
+    DateTime::from_timestamp(secs, nsecs).map(|dt| dt.naive_utc())
+}
+
+pub fn encode_ldt(time: &NaiveDateTime) -> [u8; LDT_SIZE] {
+    let nanos = time.and_utc().timestamp_nanos_opt().unwrap_or(0).max(0) as u64;
+    nanos.to_be_bytes()
+}
@@ -42,7 +42,7 @@ pub fn save_window_state_to_registry(hwnd: HWND) {
                 let maximized_val: u32 = if is_maximized { 1 } else { 0 };
                 RegSetValueExW(hkey, widestring("WindowMaximized").as_ptr(), 0, REG_DWORD, &maximized_val as *const _ as *const u8, std::mem::size_of::<u32>() as u32);
                 RegCloseKey(hkey);
-                if DEBUG { println!("Saving window state: left={}, top={}, width={}, height={}, maximized={}", left, top, width, height, is_maximized); }
+                if debug_logging() { println!("Saving window state: left={}, top={}, width={}, height={}, maximized={}", left, top, width, height, is_maximized); }
             }
         }
     }
@@ -71,7 +71,7 @@ pub fn load_window_state_from_registry() -> Option<(i32, i32, i32, i32, bool)> {
             RegQueryValueExW(hkey, widestring("WindowMaximized").as_ptr(), null_mut(), null_mut(), &mut maximized as *mut _ as *mut u8, &mut size);
 
             RegCloseKey(hkey);
-            if DEBUG { println!("Loaded window state: left={}, top={}, width={}, height={}, maximized={}", left, top, width, height, maximized != 0); }
+            if debug_logging() { println!("Loaded window state: left={}, top={}, width={}, height={}, maximized={}", left, top, width, height, maximized != 0); }
             return Some((left, top, width, height, maximized != 0));
         }
     }
@@ -0,0 +1,43 @@
+use serde::{Serialize, Deserialize};
+
+pub const LEGACY_ACK: &[u8] = b"ACK";
+pub const STATUS_RESPONSE_SIZE: usize = 12; // 3 PLC DINTs
+
+// What we send back to the PLC after every packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseFormat {
+    #[default]
+    Legacy, // The bytes "ACK", whatever happened to the packet
+    Status, // Status code, sequence number and packet code, so the PLC can retry
+}
+
+// Result of processing one packet. The values are part of the PLC protocol, don't renumber them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketStatus {
+    Stored = 0,     // Written to the database
+    Accepted = 1,   // Processed, nothing to store (e.g. keepalive)
+    Duplicate = 2,  // Sequence number already seen, dropped
+    ParseError = 10, // Packet too short or misaligned
+    DbError = 11,   // Couldn't write to the database, the PLC should resend
+}
+
+impl PacketStatus {
+    pub fn is_ok(&self) -> bool {
+        (*self as u32) < 10
+    }
+}
+
+// Status code, echoed sequence number (0 if none) and echoed packet code (0 if it couldn't be parsed)
+pub fn encode_response(format: ResponseFormat, status: PacketStatus, sequence: Option<u32>, plc_packet_code: u32) -> Vec<u8> {
+    match format {
+        ResponseFormat::Legacy => LEGACY_ACK.to_vec(),
+        ResponseFormat::Status => {
+            let mut response = Vec::with_capacity(STATUS_RESPONSE_SIZE);
+            response.extend_from_slice(&(status as u32).to_be_bytes());
+            response.extend_from_slice(&sequence.unwrap_or(0).to_be_bytes());
+            response.extend_from_slice(&plc_packet_code.to_be_bytes());
+            response
+        }
+    }
+}
@@ -0,0 +1,100 @@
+// Tracks the rolling sequence counter the PLC puts in every packet, so resent packets
+// can be dropped and lost packets can be counted.
+//
+// The PLC counts events from 1 up to SEQUENCE_MAX, then wraps back to 1.
+// Keepalives carry the last event's counter without incrementing it, and carry 0
+// if the PLC hasn't sent an event since it started.
+
+pub const SEQUENCE_MAX: u32 = i32::MAX as u32; // Largest positive DINT
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequenceCheck {
+    InOrder,
+    Duplicate,
+    Gap { expected: u32, received: u32, missing: u32 },
+    Restarted, // The PLC's counter started again from the beginning
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SequenceTracker {
+    last: Option<u32>,
+    pub duplicates: u64,
+    pub lost: u64,
+}
+
+pub fn next_sequence(sequence: u32) -> u32 {
+    if sequence >= SEQUENCE_MAX { 1 } else { sequence + 1 }
+}
+
+impl SequenceTracker {
+    // `last` is the last sequence number stored for this PLC, if any.
+    pub fn new(last: Option<u32>) -> Self {
+        Self {
+            last,
+            ..Default::default()
+        }
+    }
+
+    // How far `sequence` is ahead of the last one seen, or None if it's behind or the same.
+    fn distance_ahead(last: u32, sequence: u32) -> Option<u32> {
+        if last == 0 {
+            return if sequence > 0 { Some(sequence) } else { None };
+        }
+        let distance = ((sequence as u64 + SEQUENCE_MAX as u64 - last as u64) % SEQUENCE_MAX as u64) as u32;
+        if distance > 0 && distance < SEQUENCE_MAX / 2 {
+            Some(distance)
+        } else {
+            None
+        }
+    }
+
+    pub fn check_event(&mut self, sequence: u32) -> SequenceCheck {
+        let Some(last) = self.last else {
+            // Nothing to compare with yet
+            self.last = Some(sequence);
+            return SequenceCheck::InOrder;
+        };
+        let expected = next_sequence(last);
+        if sequence == expected {
+            self.last = Some(sequence);
+            return SequenceCheck::InOrder;
+        }
+        if sequence == 1 {
+            self.last = Some(sequence);
+            return SequenceCheck::Restarted;
+        }
+        match Self::distance_ahead(last, sequence) {
+            Some(distance) => {
+                let missing = distance - 1;
+                self.last = Some(sequence);
+                self.lost += missing as u64;
+                SequenceCheck::Gap { expected, received: sequence, missing }
+            }
+            None => {
+                self.duplicates += 1;
+                SequenceCheck::Duplicate
+            }
+        }
+    }
+
+    pub fn check_keepalive(&mut self, sequence: u32) -> SequenceCheck {
+        if sequence == 0 {
+            let restarted = matches!(self.last, Some(last) if last != 0);
+            self.last = Some(0);
+            return if restarted { SequenceCheck::Restarted } else { SequenceCheck::InOrder };
+        }
+        let Some(last) = self.last else {
+            self.last = Some(sequence);
+            return SequenceCheck::InOrder;
+        };
+        match Self::distance_ahead(last, sequence) {
+            Some(missing) => {
+                // Every event up to and including the keepalive's counter went missing
+                self.last = Some(sequence);
+                self.lost += missing as u64;
+                SequenceCheck::Gap { expected: next_sequence(last), received: sequence, missing }
+            }
+            None => SequenceCheck::InOrder,
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::fmt;
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use serde::{Serialize, Deserialize, Serializer, Deserializer};
+
+// Identifies a server for as long as it's in the config, whatever its position in the list.
+// Written to config.xml as a UUID, e.g. "3f2b8c1e-5d4a-4e7f-9b06-2a1c8d3e4f50".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ServerId([u8; 16]);
+
+impl ServerId {
+    // A random (version 4) UUID
+    pub fn new() -> Self {
+        ServerId(*uuid::Uuid::new_v4().as_bytes())
+    }
+
+    // Accepts a UUID, with or without hyphens and braces, or an id from an older config.xml.
+    pub fn parse(id: &str) -> Result<Self, &'static str> {
+        let id = id.trim();
+        let hex: String = id.trim_matches(|c| c == '{' || c == '}').chars().filter(|&c| c != '-').collect();
+        if hex.len() == 32 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            let mut bytes = [0u8; 16];
+            for (i, byte) in bytes.iter_mut().enumerate() {
+                *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| "Server id isn't hex")?;
+            }
+            return Ok(ServerId(bytes));
+        }
+        let legacy = STANDARD.decode(id).map_err(|_| "Server id is neither a UUID nor a base64 legacy id")?;
+        Ok(Self::from_legacy(&legacy))
+    }
+
+    // Older versions stored the creation time in nanoseconds as text, zero padded to 32 bytes.
+    // Always gives the same id for the same legacy id, so the server keeps its identity.
+    pub fn from_legacy(legacy: &[u8]) -> Self {
+        let text: String = legacy.iter().take_while(|&&b| b != 0).map(|&b| b as char).collect();
+        let bytes = match text.parse::<u128>() {
+            Ok(nanos) => nanos.to_be_bytes(),
+            Err(_) => {
+                // Not a timestamp, fold whatever it is into 16 bytes
+                let mut bytes = [0u8; 16];
+                for (i, b) in legacy.iter().enumerate() {
+                    bytes[i % 16] ^= b;
+                }
+                bytes
+            }
+        };
+        Self::with_version(bytes, 8) // Version 8 is for UUIDs made some other way
+    }
+
+    fn with_version(mut bytes: [u8; 16], version: u8) -> Self {
+        bytes[6] = (bytes[6] & 0x0F) | (version << 4);
+        bytes[8] = (bytes[8] & 0x3F) | 0x80; // RFC 4122 variant
+        ServerId(bytes)
+    }
+}
+
+impl fmt::Display for ServerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if matches!(i, 4 | 6 | 8 | 10) {
+                write!(f, "-")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for ServerId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for ServerId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let id = <String as Deserialize>::deserialize(deserializer)?;
+        ServerId::parse(&id).map_err(serde::de::Error::custom)
+    }
+}
@@ -0,0 +1,490 @@
+// Pretends to be a PLC, for trying the logger out without the TIA project.
+// Connects to a server like a TCON active connection does, and sends packets built the way
+// that server expects them (framing, sequence numbers, timestamps), waiting for each response.
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use chrono::Local;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{sleep, sleep_until, timeout, Instant};
+use crate::comms_tcon::{ConnectionMode, ServerEntry, Transport};
+use crate::constants::*;
+use crate::event_data::{PacketLayout, PlcRuntimeConfig};
+use crate::framing::{FramingMode, LENGTH_HEADER_SIZE, MAX_PACKET_SIZE};
+use crate::plc_time::{encode_dtl, encode_ldt, TimestampFormat};
+use crate::response::{PacketStatus, ResponseFormat, LEGACY_ACK, STATUS_RESPONSE_SIZE};
+use crate::sequence::next_sequence;
+use crate::utils::{log, log_warn, Backoff};
+
+const RESPONSE_TIMEOUT_SECS: u64 = 5;
+const RESEND_DELAY_SECS: u64 = 1; // After a database error response
+
+#[derive(Debug, Clone)]
+pub struct SimulatorConfig {
+    pub address: String, // host:port of the server
+    pub framing: FramingMode,
+    pub record_length: usize,
+    pub layout: PacketLayout,
+    pub response_format: ResponseFormat,
+    pub keepalive_interval: Option<Duration>, // None sends no keepalives
+    pub plc_config: PlcRuntimeConfig, // Sent in the configuration packet
+}
+
+impl SimulatorConfig {
+    // Builds packets the way `server` expects them. A server listening on every address is reached on localhost.
+    pub fn from_server(server: &ServerEntry) -> Result<Self, &'static str> {
+        if server.transport != Transport::Tcp {
+            return Err("The simulator only speaks plain TCP");
+        }
+        if server.connection_mode != ConnectionMode::Listen {
+            return Err("The simulator connects to the server, so the server's connection_mode must be listen");
+        }
+        let host = match server.ip_address.trim() {
+            "0.0.0.0" | "" => "127.0.0.1",
+            "::" => "::1",
+            ip => ip,
+        };
+        let address = match host.parse::<std::net::IpAddr>() {
+            Ok(std::net::IpAddr::V6(_)) => format!("[{}]:{}", host, server.port),
+            _ => format!("{}:{}", host, server.port),
+        };
+        Ok(SimulatorConfig {
+            address,
+            framing: server.framing,
+            record_length: server.record_length,
+            layout: server.packet_layout(),
+            response_format: server.response_format,
+            ..Self::new("")
+        })
+    }
+
+    // Raw framing, no sequence numbers or timestamps, legacy responses, keepalives every 10 s
+    pub fn new(address: &str) -> Self {
+        SimulatorConfig {
+            address: address.to_string(),
+            framing: FramingMode::Raw,
+            record_length: 0,
+            layout: PacketLayout::default(),
+            response_format: ResponseFormat::Legacy,
+            keepalive_interval: Some(Duration::from_secs(10)),
+            plc_config: PlcRuntimeConfig {
+                downtime_db: 100,
+                seconds_before_downtime: DEFAULT_SECONDS_BEFORE_DOWNTIME,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SimPacket {
+    Keepalive,
+    Event { code: u32, data: Vec<u32> }, // General event (type 50)
+    Alarm { number: u32, data: Vec<u32> }, // Type 9
+    DowntimeStart,
+    DowntimeEnd,
+    Config, // Uses SimulatorConfig::plc_config
+}
+
+impl SimPacket {
+    fn describe(&self) -> String {
+        match self {
+            SimPacket::Keepalive => "keepalive".to_string(),
+            SimPacket::Event { code, .. } => format!("event {}", code),
+            SimPacket::Alarm { number, .. } => format!("alarm {}", number),
+            SimPacket::DowntimeStart => "downtime start".to_string(),
+            SimPacket::DowntimeEnd => "downtime end".to_string(),
+            SimPacket::Config => "config".to_string(),
+        }
+    }
+}
+
+// Turns packets into bytes, keeping the sequence counter like the PLC does.
+pub struct PacketEncoder {
+    framing: FramingMode,
+    record_length: usize,
+    layout: PacketLayout,
+    plc_config: PlcRuntimeConfig,
+    last_sequence: u32, // 0 until the first event
+}
+
+impl PacketEncoder {
+    pub fn new(config: &SimulatorConfig) -> Self {
+        PacketEncoder {
+            framing: config.framing,
+            record_length: config.record_length,
+            layout: config.layout,
+            plc_config: config.plc_config,
+            last_sequence: 0,
+        }
+    }
+
+    // The bytes for one TSEND, including any length prefix or record padding.
+    pub fn encode(&mut self, packet: &SimPacket) -> Result<Vec<u8>, &'static str> {
+        let (data_type, code, data) = match packet {
+            SimPacket::Keepalive => (EVENT_TYPE_KEEPALIVE, 0, vec![]),
+            SimPacket::Event { code, data } => (EVENT_TYPE_PLC, *code, data.clone()),
+            SimPacket::Alarm { number, data } => (EVENT_TYPE_ALARM, *number, data.clone()),
+            SimPacket::DowntimeStart => (EVENT_TYPE_SPECIAL, PACKET_CODE_DOWNTIME_START, vec![]),
+            SimPacket::DowntimeEnd => (EVENT_TYPE_SPECIAL, PACKET_CODE_DOWNTIME_END, vec![]),
+            SimPacket::Config => (
+                EVENT_TYPE_SPECIAL,
+                PACKET_CODE_CONFIG,
+                vec![self.plc_config.downtime_db, self.plc_config.seconds_before_downtime],
+            ),
+        };
+
+        let mut bytes = Vec::with_capacity(self.layout.header_size() + data.len() * 4);
+        bytes.extend_from_slice(&data_type.to_be_bytes());
+        bytes.extend_from_slice(&code.to_be_bytes());
+        if self.layout.sequence {
+            // Keepalives repeat the last event's counter
+            if *packet != SimPacket::Keepalive {
+                self.last_sequence = next_sequence(self.last_sequence);
+            }
+            bytes.extend_from_slice(&self.last_sequence.to_be_bytes());
+        }
+        let now = Local::now().naive_local();
+        match self.layout.timestamp {
+            TimestampFormat::None => {}
+            TimestampFormat::Dtl => bytes.extend_from_slice(&encode_dtl(&now)),
+            TimestampFormat::Ldt => bytes.extend_from_slice(&encode_ldt(&now)),
+        }
+        for value in data {
+            bytes.extend_from_slice(&value.to_be_bytes());
+        }
+        if bytes.len() > MAX_PACKET_SIZE {
+            return Err("Packet is larger than 512 bytes");
+        }
+
+        match self.framing {
+            FramingMode::Raw => Ok(bytes),
+            FramingMode::LengthPrefixed => {
+                let mut framed = Vec::with_capacity(LENGTH_HEADER_SIZE + bytes.len());
+                framed.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+                framed.extend_from_slice(&bytes);
+                Ok(framed)
+            }
+            FramingMode::FixedRecord => {
+                if bytes.len() > self.record_length {
+                    return Err("Packet is longer than the server's record_length");
+                }
+                bytes.resize(self.record_length, 0); // Unused DINTs are zero, like an unused array in the PLC's send buffer
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reply {
+    Ack, // Legacy response, says nothing about what happened
+    Status { status: u32, sequence: u32, plc_packet_code: u32 },
+}
+
+impl Reply {
+    // Whether the packet needs sending again
+    pub fn is_db_error(&self) -> bool {
+        matches!(self, Reply::Status { status, .. } if *status == PacketStatus::DbError as u32)
+    }
+}
+
+// What happens next, and how long to wait before it
+pub trait Scenario {
+    fn next_step(&mut self) -> Option<(Duration, SimPacket)>; // None when finished
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    Send(SimPacket),
+    Wait(Duration),
+    Repeat,
+}
+
+// A scenario read from a text file, one step per line:
+//   config | keepalive | downtime_start | downtime_end
+//   event <code> [data...] | alarm <number> [data...]
+//   wait <seconds> | repeat
+// Blank lines and anything after a # are ignored.
+#[derive(Debug, Clone)]
+pub struct ScriptScenario {
+    steps: Vec<Step>,
+    position: usize,
+}
+
+impl ScriptScenario {
+    pub fn parse(script: &str) -> Result<Self, String> {
+        let mut steps = Vec::new();
+        for (number, line) in script.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let command = words.next().unwrap_or("");
+            let numbers: Result<Vec<u32>, _> = words.clone().map(|w| w.parse::<u32>()).collect();
+            let error = |message: &str| format!("Line {}: {} ({})", number + 1, message, line);
+            let step = match command {
+                "config" => Step::Send(SimPacket::Config),
+                "keepalive" => Step::Send(SimPacket::Keepalive),
+                "downtime_start" => Step::Send(SimPacket::DowntimeStart),
+                "downtime_end" => Step::Send(SimPacket::DowntimeEnd),
+                "event" | "alarm" => {
+                    let numbers = numbers.map_err(|_| error("expected whole numbers"))?;
+                    let (&code, data) = numbers.split_first().ok_or_else(|| error("expected a code"))?;
+                    let data = data.to_vec();
+                    if command == "event" {
+                        Step::Send(SimPacket::Event { code, data })
+                    } else {
+                        Step::Send(SimPacket::Alarm { number: code, data })
+                    }
+                }
+                "wait" => {
+                    let seconds = words.next().and_then(|w| w.parse::<f64>().ok()).filter(|s| *s >= 0.0);
+                    Step::Wait(Duration::from_secs_f64(seconds.ok_or_else(|| error("expected a number of seconds"))?))
+                }
+                "repeat" => Step::Repeat,
+                _ => return Err(error("unknown command")),
+            };
+            steps.push(step);
+        }
+        let sends_before_repeat = steps.iter().take_while(|s| **s != Step::Repeat).any(|s| matches!(s, Step::Send(_)));
+        if steps.contains(&Step::Repeat) && !sends_before_repeat {
+            return Err("A script that repeats has to send something first".to_string());
+        }
+        Ok(ScriptScenario { steps, position: 0 })
+    }
+}
+
+impl Scenario for ScriptScenario {
+    fn next_step(&mut self) -> Option<(Duration, SimPacket)> {
+        let mut wait = Duration::ZERO;
+        loop {
+            match self.steps.get(self.position)?.clone() {
+                Step::Send(packet) => {
+                    self.position += 1;
+                    return Some((wait, packet));
+                }
+                Step::Wait(duration) => {
+                    self.position += 1;
+                    wait += duration;
+                }
+                Step::Repeat => self.position = 0,
+            }
+        }
+    }
+}
+
+// Events, alarms and downtime at random times, at the given average rates. Never finishes.
+#[derive(Debug, Clone)]
+pub struct RandomScenario {
+    pub events_per_min: f64,
+    pub alarms_per_min: f64,
+    pub downtimes_per_hour: f64,
+    pub downtime_secs: (u64, u64), // Shortest and longest downtime
+    rng: Rng,
+    in_downtime: bool,
+}
+
+impl RandomScenario {
+    pub fn new(events_per_min: f64, alarms_per_min: f64, downtimes_per_hour: f64, seed: u64) -> Self {
+        RandomScenario {
+            events_per_min,
+            alarms_per_min,
+            downtimes_per_hour,
+            downtime_secs: (30, 600),
+            rng: Rng::new(seed),
+            in_downtime: false,
+        }
+    }
+}
+
+impl Scenario for RandomScenario {
+    fn next_step(&mut self) -> Option<(Duration, SimPacket)> {
+        if self.in_downtime {
+            // The machine is stopped, so nothing happens until it starts again
+            self.in_downtime = false;
+            let (shortest, longest) = self.downtime_secs;
+            let seconds = shortest + self.rng.below(longest.saturating_sub(shortest) + 1);
+            return Some((Duration::from_secs(seconds), SimPacket::DowntimeEnd));
+        }
+        let events = self.events_per_min.max(0.0) / 60.0;
+        let alarms = self.alarms_per_min.max(0.0) / 60.0;
+        let downtimes = self.downtimes_per_hour.max(0.0) / 3600.0;
+        let total = events + alarms + downtimes; // Per second
+        if total <= 0.0 {
+            return None;
+        }
+        // Time to the next of several random (Poisson) streams, and which one it was
+        let wait = Duration::from_secs_f64(-(1.0 - self.rng.fraction()).ln() / total);
+        let pick = self.rng.fraction() * total;
+        let packet = if pick < events {
+            let data = (0..3).map(|_| self.rng.below(1000) as u32).collect();
+            SimPacket::Event { code: 1 + self.rng.below(20) as u32, data }
+        } else if pick < events + alarms {
+            SimPacket::Alarm { number: 1 + self.rng.below(50) as u32, data: vec![] }
+        } else {
+            self.in_downtime = true;
+            SimPacket::DowntimeStart
+        };
+        Some((wait, packet))
+    }
+}
+
+// xorshift64*, plenty for picking event codes. The same seed gives the same scenario.
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed.max(1))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    // 0.0 up to but not including 1.0
+    fn fraction(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn below(&mut self, limit: u64) -> u64 {
+        if limit == 0 { 0 } else { self.next_u64() % limit }
+    }
+}
+
+// A seed that's different every run
+pub fn time_seed() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(1)
+}
+
+pub struct Simulator {
+    config: SimulatorConfig,
+    encoder: PacketEncoder,
+    stream: Option<TcpStream>,
+    backoff: Backoff,
+    next_keepalive: Instant,
+}
+
+impl Simulator {
+    pub fn new(config: SimulatorConfig) -> Self {
+        Simulator {
+            encoder: PacketEncoder::new(&config),
+            stream: None,
+            backoff: Backoff::new(Duration::from_secs(RECONNECT_DELAY_MIN_SECS), Duration::from_secs(RECONNECT_DELAY_MAX_SECS)),
+            next_keepalive: Instant::now(),
+            config,
+        }
+    }
+
+    // Follows the scenario until it finishes, keeping the connection alive in between.
+    pub async fn run(&mut self, scenario: &mut dyn Scenario) {
+        while let Some((wait, packet)) = scenario.next_step() {
+            self.keepalives_until(Instant::now() + wait).await;
+            self.send(&packet).await;
+        }
+    }
+
+    // Just keepalives, until the task is dropped
+    pub async fn idle(&mut self) {
+        if self.config.keepalive_interval.is_none() {
+            std::future::pending::<()>().await;
+        }
+        loop {
+            self.keepalives_until(Instant::now() + Duration::from_secs(3600)).await;
+        }
+    }
+
+    async fn keepalives_until(&mut self, deadline: Instant) {
+        if let Some(interval) = self.config.keepalive_interval {
+            while self.next_keepalive <= deadline {
+                sleep_until(self.next_keepalive).await;
+                self.send(&SimPacket::Keepalive).await;
+                self.next_keepalive = Instant::now() + interval;
+            }
+        }
+        sleep_until(deadline).await;
+    }
+
+    // Sends one packet, reconnecting and resending until the server has it.
+    pub async fn send(&mut self, packet: &SimPacket) -> Reply {
+        // Connect first, so the configuration packet sent on connect gets the earlier sequence number
+        if self.stream.is_none() {
+            self.connect().await;
+        }
+        let bytes = match self.encoder.encode(packet) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                log_warn(&format!("Can't send {}: {}", packet.describe(), e));
+                return Reply::Ack;
+            }
+        };
+        loop {
+            if self.stream.is_none() {
+                self.connect().await;
+            }
+            match self.exchange(&bytes).await {
+                Ok(reply) if reply.is_db_error() => {
+                    log_warn(&format!("Server couldn't store {}, resending", packet.describe()));
+                    sleep(Duration::from_secs(RESEND_DELAY_SECS)).await;
+                }
+                Ok(reply) => {
+                    log(&format!("Sent {}: {:?}", packet.describe(), reply));
+                    self.backoff.reset();
+                    return reply;
+                }
+                Err(e) => {
+                    log_warn(&format!("Lost the connection sending {}: {}", packet.describe(), e));
+                    self.stream = None;
+                    sleep(self.backoff.next_delay()).await;
+                }
+            }
+        }
+    }
+
+    // Connects, retrying with a growing delay, then sends the configuration packet like the PLC does.
+    async fn connect(&mut self) {
+        loop {
+            match timeout(Duration::from_secs(CONNECT_TIMEOUT_SECS), TcpStream::connect(&self.config.address)).await {
+                Ok(Ok(stream)) => {
+                    log(&format!("Connected to {}", self.config.address));
+                    self.stream = Some(stream);
+                    break;
+                }
+                Ok(Err(e)) => log_warn(&format!("Can't connect to {}: {}", self.config.address, e)),
+                Err(_) => log_warn(&format!("Can't connect to {}: timed out", self.config.address)),
+            }
+            sleep(self.backoff.next_delay()).await;
+        }
+        if let Ok(bytes) = self.encoder.encode(&SimPacket::Config) {
+            if let Err(e) = self.exchange(&bytes).await {
+                log_warn(&format!("Lost the connection sending config: {}", e));
+                self.stream = None; // The packet being sent finds this and reconnects
+            }
+        }
+    }
+
+    async fn exchange(&mut self, bytes: &[u8]) -> std::io::Result<Reply> {
+        let Some(stream) = self.stream.as_mut() else {
+            return Err(std::io::ErrorKind::NotConnected.into());
+        };
+        stream.write_all(bytes).await?;
+        let response_size = match self.config.response_format {
+            ResponseFormat::Legacy => LEGACY_ACK.len(),
+            ResponseFormat::Status => STATUS_RESPONSE_SIZE,
+        };
+        let mut response = vec![0u8; response_size];
+        timeout(Duration::from_secs(RESPONSE_TIMEOUT_SECS), stream.read_exact(&mut response))
+            .await
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "No response"))??;
+        Ok(match self.config.response_format {
+            ResponseFormat::Legacy => Reply::Ack,
+            ResponseFormat::Status => {
+                let dint = |i: usize| u32::from_be_bytes([response[i], response[i + 1], response[i + 2], response[i + 3]]);
+                Reply::Status { status: dint(0), sequence: dint(4), plc_packet_code: dint(8) }
+            }
+        })
+    }
+}
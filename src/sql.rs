@@ -1,36 +1,227 @@
-use rusqlite::{params, Connection, Result};
+use rusqlite::{params, Connection, OptionalExtension, Result};
 use serde_json;
 use std::path::Path;
-use crate::constants::DEBUG;
-use crate::event_data::{EventDataPacket, SqlDataPacket};
+use std::sync::RwLock;
+use once_cell::sync::Lazy;
+use crate::comms_s7::WriteAction;
+use crate::constants::DEFAULT_DB_PATH;
+use crate::constants::{EVENT_TYPE_SPECIAL, PACKET_CODE_DOWNTIME_END, PACKET_CODE_DOWNTIME_START};
+use crate::event_data::{DowntimeSnapshot, EventDataPacket, EventSnapshot, PlcRuntimeConfig, SqlDataPacket};
+use crate::payload_schema::NamedValue;
 use crate::utils::*;
 
+// Schema changes applied in order on top of schema.sql.
+// The database's user_version records how many of them have been applied.
+const MIGRATIONS: &[&str] = &[
+    include_str!("migrations/001_data_values.sql"),
+    include_str!("migrations/002_plc_config.sql"),
+    include_str!("migrations/003_sequence.sql"),
+    include_str!("migrations/004_plc_timestamp.sql"),
+    include_str!("migrations/005_downtime_snapshot.sql"),
+    include_str!("migrations/006_event_snapshot.sql"),
+    include_str!("migrations/007_clock_drift.sql"),
+    include_str!("migrations/008_write_audit.sql"),
+];
+
+static DB_PATH: Lazy<RwLock<String>> = Lazy::new(|| RwLock::new(DEFAULT_DB_PATH.to_string()));
+
+// Every connection opened after this uses the new path
+pub fn set_db_path(path: &str) {
+    *DB_PATH.write().unwrap() = path.to_string();
+}
+
+pub fn db_path() -> String {
+    DB_PATH.read().unwrap().clone()
+}
+
 pub fn connect_to_db() -> Result<Connection> {
-    let db_path = "event_data.db";
-    let is_new_db = !Path::new(db_path).exists();
+    let db_path = db_path();
+    let is_new_db = !Path::new(&db_path).exists();
 
-    let conn = Connection::open(db_path).expect("Failed to open database");
+    let conn = Connection::open(&db_path)?;
 
     if is_new_db {
-        conn.execute_batch(include_str!("schema.sql")).expect("Failed to create schema");
-        if DEBUG { log("Database created with schema."); }
+        conn.execute_batch(include_str!("schema.sql"))?;
+        if debug_logging() { log("Database created with schema."); }
     } else {
-        if DEBUG { log("Connected to existing database."); }
+        if debug_logging() { log("Connected to existing database."); }
     }
+    migrate(&conn)?;
     Ok(conn)
 }
 
-pub fn store_packet(conn: &Connection, packet: &EventDataPacket, sender: &String) -> rusqlite::Result<()> {
+fn migrate(conn: &Connection) -> Result<()> {
+    let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        conn.execute_batch(migration)?;
+        conn.pragma_update(None, "user_version", (i + 1) as i64)?;
+        log(&format!("Database migrated to version {}", i + 1));
+    }
+    Ok(())
+}
+
+fn values_to_json(values: &[NamedValue]) -> Option<String> {
+    if values.is_empty() {
+        None
+    } else {
+        serde_json::to_string(values).ok()
+    }
+}
+
+fn values_from_json(values_json: Option<String>) -> Vec<NamedValue> {
+    values_json
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+// plc_timestamp is stored as RFC 3339, like timestamp
+fn time_from_column(time: Option<&str>) -> Option<chrono::DateTime<chrono::Local>> {
+    time.and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok())
+        .map(|t| t.with_timezone(&chrono::Local))
+}
+
+// Returns the new row's id, for linking snapshots to it
+pub fn store_packet(conn: &Connection, packet: &EventDataPacket, sender: &String) -> rusqlite::Result<i64> {
     let timestamp = chrono::Local::now().to_rfc3339();
     let data_json = serde_json::to_string(&packet.data).unwrap();
+    let values_json = values_to_json(&packet.values);
+    let plc_timestamp = packet.plc_timestamp.map(|t| t.to_rfc3339());
+
+    conn.execute(
+        "INSERT INTO event_data (plc, timestamp, data_type, plc_packet_code, data, data_values, sequence, plc_timestamp) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![sender, timestamp, packet.data_type, packet.plc_packet_code, data_json, values_json, packet.sequence, plc_timestamp],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+pub fn store_event_snapshot(conn: &Connection, event_id: i64, db: u16, start: u16, data: &[u8], values: &[NamedValue], sender: &String) -> rusqlite::Result<()> {
+    let timestamp = chrono::Local::now().to_rfc3339();
+    conn.execute(
+        "INSERT INTO event_snapshot (event_id, plc, timestamp, db, start, data, data_values) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![event_id, sender, timestamp, db, start, data, values_to_json(values)],
+    )?;
+    Ok(())
+}
+
+// Every snapshot stored for an event, in the order they were read.
+pub fn query_event_snapshots(conn: &Connection, event_id: i64) -> rusqlite::Result<Vec<EventSnapshot>> {
+    let mut stmt = conn.prepare(
+        "SELECT event_id, timestamp, db, start, data, data_values FROM event_snapshot WHERE event_id = ?1 ORDER BY id",
+    )?;
+    let snapshots = stmt.query_map(params![event_id], |row| {
+        Ok(EventSnapshot {
+            event_id: row.get(0)?,
+            timestamp: row.get(1)?,
+            db: row.get(2)?,
+            start: row.get(3)?,
+            data: row.get(4)?,
+            values: values_from_json(row.get(5)?),
+        })
+    })?;
+    snapshots.collect()
+}
+
+// The sequence number of the last packet stored for a PLC, so gaps can be detected across restarts.
+pub fn last_sequence(conn: &Connection, plc: &str) -> rusqlite::Result<Option<u32>> {
+    conn.query_row(
+        "SELECT sequence FROM event_data WHERE plc = ?1 AND sequence IS NOT NULL ORDER BY id DESC LIMIT 1",
+        params![plc],
+        |row| row.get(0),
+    ).optional()
+}
+
+pub fn store_plc_config(conn: &Connection, config: &PlcRuntimeConfig, sender: &String) -> rusqlite::Result<()> {
+    let timestamp = chrono::Local::now().to_rfc3339();
+    conn.execute(
+        "INSERT INTO plc_config (plc, timestamp, downtime_db, seconds_before_downtime) VALUES (?1, ?2, ?3, ?4)",
+        params![sender, timestamp, config.downtime_db, config.seconds_before_downtime],
+    )?;
+    Ok(())
+}
+
+// The last configuration packet received from a PLC, if it has ever sent one.
+pub fn latest_plc_config(conn: &Connection, plc: &str) -> rusqlite::Result<Option<PlcRuntimeConfig>> {
+    conn.query_row(
+        "SELECT downtime_db, seconds_before_downtime FROM plc_config WHERE plc = ?1 ORDER BY id DESC LIMIT 1",
+        params![plc],
+        |row| Ok(PlcRuntimeConfig {
+            downtime_db: row.get(0)?,
+            seconds_before_downtime: row.get(1)?,
+        }),
+    ).optional()
+}
+
+// The code of the last downtime start or end stored for a PLC, if there has been one.
+pub fn last_downtime_event(conn: &Connection, plc: &str) -> rusqlite::Result<Option<u32>> {
+    conn.query_row(
+        "SELECT plc_packet_code FROM event_data WHERE plc = ?1 AND data_type = ?2 AND plc_packet_code IN (?3, ?4) ORDER BY id DESC LIMIT 1",
+        params![plc, EVENT_TYPE_SPECIAL, PACKET_CODE_DOWNTIME_START, PACKET_CODE_DOWNTIME_END],
+        |row| row.get(0),
+    ).optional()
+}
+
+// `filled_in` is the code of the downtime event stored because of this snapshot, if any.
+pub fn store_downtime_snapshot(conn: &Connection, snapshot: &DowntimeSnapshot, downtime_db: u16, filled_in: Option<u32>, sender: &String) -> rusqlite::Result<()> {
+    let timestamp = chrono::Local::now().to_rfc3339();
+    conn.execute(
+        "INSERT INTO downtime_snapshot (plc, timestamp, downtime_db, in_downtime, reason_code, downtime_count, total_downtime_secs, current_downtime_secs, filled_in) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![sender, timestamp, downtime_db, snapshot.in_downtime, snapshot.reason_code, snapshot.downtime_count,
+                snapshot.total_downtime_secs, snapshot.current_downtime_secs, filled_in],
+    )?;
+    Ok(())
+}
 
+pub fn store_clock_drift(conn: &Connection, plc_time: &chrono::NaiveDateTime, drift_ms: i64, sender: &String) -> rusqlite::Result<()> {
+    let timestamp = chrono::Local::now().to_rfc3339();
     conn.execute(
-        "INSERT INTO event_data (plc, timestamp, data_type, plc_packet_code, data) VALUES (?1, ?2, ?3, ?4, ?5)",
-        params![sender, timestamp, packet.data_type, packet.plc_packet_code, data_json],
+        "INSERT INTO clock_drift (plc, timestamp, plc_time, drift_ms) VALUES (?1, ?2, ?3, ?4)",
+        params![sender, timestamp, plc_time.format("%Y-%m-%dT%H:%M:%S%.3f").to_string(), drift_ms],
     )?;
     Ok(())
 }
 
+// `data` is None and `error` is set when the write failed
+pub fn store_write_audit(conn: &Connection, action: &WriteAction, value: &str, data: Option<&[u8]>, error: Option<&str>, sender: &String) -> rusqlite::Result<()> {
+    let timestamp = chrono::Local::now().to_rfc3339();
+    conn.execute(
+        "INSERT INTO write_audit (plc, timestamp, action, address, value, data, user, error) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![sender, timestamp, action.name, action.address(), value, data, current_user(), error],
+    )?;
+    Ok(())
+}
+
+// Most recent events from every PLC, newest first.
+pub fn query_recent_packets(sql_conn: &Connection, limit: usize) -> rusqlite::Result<Vec<SqlDataPacket>> {
+    let sql = "SELECT plc, timestamp, data_type, plc_packet_code, data, data_values, sequence, plc_timestamp, id \
+               FROM event_data \
+               ORDER BY id DESC \
+               LIMIT ?1";
+    let mut stmt = sql_conn.prepare(sql)?;
+    let packet_iter = stmt.query_map(params![limit as i64], |row| {
+        let data_json: String = row.get(4)?;
+        let plc_timestamp: Option<String> = row.get(7)?;
+        Ok(SqlDataPacket {
+            id: row.get(8)?,
+            query: sql.to_string(),
+            plc: row.get(0)?,
+            timestamp: row.get(1)?,
+            plc_timestamp: plc_timestamp.clone(),
+            packet: EventDataPacket {
+                raw: vec![],
+                data_type: row.get(2)?,
+                plc_packet_code: row.get(3)?,
+                sequence: row.get(6)?,
+                plc_timestamp: time_from_column(plc_timestamp.as_deref()),
+                data: serde_json::from_str(&data_json).unwrap_or_default(),
+                values: values_from_json(row.get(5)?),
+            },
+        })
+    })?;
+
+    packet_iter.collect()
+}
+
 pub fn query_packets(sql_conn: &Connection, plc: &str, start_date: &str, end_date: &str, data_type: &str, plc_packet_code: &str) -> rusqlite::Result<Vec<SqlDataPacket>> {
     // build a safe IN(...) clause from comma‑separated PLC codes
     let codes_vec: Vec<u32> = plc_packet_code
@@ -52,15 +243,16 @@ pub fn query_packets(sql_conn: &Connection, plc: &str, start_date: &str, end_dat
     // column stores a full RFC3339 string, so equality on that string will
     // almost never match when only a date is supplied.  Converting the
     // column to a date with `date(timestamp)` makes single‑day queries behave
-    // properly.  An empty `end_date` means "no upper bound".
+    // properly.  An empty `end_date` means "no upper bound".  The PLC's own
+    // timestamp is used when it sent one, so buffered events land on the right day.
 
     if end_date.is_empty() {
         // open‑ended query (no upper limit)
         let sql = format!(
-            "SELECT plc, timestamp, data_type, plc_packet_code, data \
+            "SELECT plc, timestamp, data_type, plc_packet_code, data, data_values, plc_timestamp, sequence, id \
              FROM event_data \
              WHERE plc = ?1 \
-               AND date(timestamp, 'localtime') >= date(?2) \
+               AND date(COALESCE(plc_timestamp, timestamp), 'localtime') >= date(?2) \
                AND data_type = ?3 \
                AND plc_packet_code {}",
             in_clause
@@ -75,21 +267,31 @@ pub fn query_packets(sql_conn: &Connection, plc: &str, start_date: &str, end_dat
             let data_json: String = row.get(4)?;
             let query = format!(
                 "SELECT plc, timestamp, data_type, plc_packet_code, data FROM event_data \
-                 WHERE plc = {} AND date(timestamp, 'localtime') >= date('{}') AND data_type = {} AND plc_packet_code {}",
+                 WHERE plc = {} AND date(COALESCE(plc_timestamp, timestamp), 'localtime') >= date('{}') AND data_type = {} AND plc_packet_code {}",
                 plc, start_date, data_type, in_clause
             );
             let data_vec: Vec<u32> = serde_json::from_str(&data_json).unwrap_or_default();
+            let values = values_from_json(row.get(5)?);
+            let plc_timestamp: Option<String> = row.get(6)?;
+            let sequence: Option<u32> = row.get(7)?;
+            let id: i64 = row.get(8)?;
+            let plc_time = time_from_column(plc_timestamp.as_deref());
             println!("Results: {}", data_vec.len());
 
             Ok(SqlDataPacket {
+                id,
                 query,
                 plc: plc.to_string(),
                 timestamp,
+                plc_timestamp,
                 packet: EventDataPacket {
                     raw: vec![],
                     data_type,
                     plc_packet_code,
+                    sequence,
+                    plc_timestamp: plc_time,
                     data: data_vec,
+                    values,
                 },
             })
         })?;
@@ -98,11 +300,11 @@ pub fn query_packets(sql_conn: &Connection, plc: &str, start_date: &str, end_dat
     } else {
         // bounded range query
         let sql = format!(
-            "SELECT plc, timestamp, data_type, plc_packet_code, data \
+            "SELECT plc, timestamp, data_type, plc_packet_code, data, data_values, plc_timestamp, sequence, id \
              FROM event_data \
              WHERE plc = ?1 \
-               AND date(timestamp, 'localtime') >= date(?2) \
-               AND date(timestamp, 'localtime') <= date(?3) \
+               AND date(COALESCE(plc_timestamp, timestamp), 'localtime') >= date(?2) \
+               AND date(COALESCE(plc_timestamp, timestamp), 'localtime') <= date(?3) \
                AND data_type = ?4 \
                AND plc_packet_code {}",
             in_clause
@@ -126,22 +328,32 @@ pub fn query_packets(sql_conn: &Connection, plc: &str, start_date: &str, end_dat
                 let data_json: String = row.get(4)?;
                 let query = format!(
                     "SELECT plc, timestamp, data_type, plc_packet_code, data FROM event_data \
-                     WHERE plc = {} AND date(timestamp, 'localtime') >= date('{}') AND date(timestamp, 'localtime') <= date('{}') \
+                     WHERE plc = {} AND date(COALESCE(plc_timestamp, timestamp), 'localtime') >= date('{}') AND date(COALESCE(plc_timestamp, timestamp), 'localtime') <= date('{}') \
                            AND data_type = {} AND plc_packet_code {}",
                     plc, start_date, end_date, data_type, in_clause
                 );
                 let data_vec: Vec<u32> = serde_json::from_str(&data_json).unwrap_or_default();
+                let values = values_from_json(row.get(5)?);
+                let plc_timestamp: Option<String> = row.get(6)?;
+                let sequence: Option<u32> = row.get(7)?;
+                let id: i64 = row.get(8)?;
+                let plc_time = time_from_column(plc_timestamp.as_deref());
                 println!("Results: {}", data_vec.len());
 
                 Ok(SqlDataPacket {
+                    id,
                     query,
                     plc,
                     timestamp,
+                    plc_timestamp,
                     packet: EventDataPacket {
                         raw: vec![],
                         data_type,
                         plc_packet_code,
+                        sequence,
+                        plc_timestamp: plc_time,
                         data: data_vec,
+                        values,
                     },
                 })
             },
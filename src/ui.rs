@@ -7,12 +7,13 @@ use chrono::TimeZone;
 use im::Vector;
 use floem::{
     action::{exec_after, set_window_menu},
+    ext_event::create_ext_action,
     event::{Event, EventListener}, 
     kurbo::Point,
     menu::{Menu, SubMenu},
     peniko, prelude::*, 
     peniko::kurbo::Rect,
-    reactive::{UpdaterEffect, use_context, provide_context, RwSignal, ReadSignal, SignalGet, SignalUpdate, WriteSignal}, 
+    reactive::{Scope, UpdaterEffect, use_context, provide_context, RwSignal, ReadSignal, SignalGet, SignalUpdate, WriteSignal}, 
     style::{CursorStyle, Style},    
     text::Weight, 
     views::{button, container, h_stack, label, scroll, v_stack, Decorators}, 
@@ -26,21 +27,30 @@ use syntect::highlighting::{
 use syntect::parsing::Scope; //{ParseState, Scope, ScopeStack, ScopeStackOp, SyntaxReference, SyntaxSet, };
 use syntect_assets::assets::HighlightingAssets;
 use crate::app_config::{AppCommand, /*AppConfig,*/ ThemeNameSig};
-use crate::comms_tcon::{ServerEntry, ServerStatus, ServerCommand, generate_server_id};
+use crate::comms_s7::WriteAction;
+use crate::comms_tcon::{ConnectionMode, ServerConfig, ServerEntry, ServerStatus, ServerStatusInfo, ServerCommand, Transport};
 use crate::constants::*;
 use crate::downtime::*;
+use crate::event_data::{EventSnapshot, SqlDataPacket};
+use crate::framing::FramingMode;
+use crate::payload_schema::format_values;
+use crate::server_id::ServerId;
+use crate::sql::{connect_to_db, query_event_snapshots, query_recent_packets};
 use crate::filehandling::file_tail;
-use crate::{SERVER_CONFIG, ServerStatusInfo, mpsc::Receiver};
+use std::sync::mpsc::Receiver;
+use crate::config_store::{config_path, SERVER_CONFIG};
 use crate::utils::*;
 use std::fs;
 use std::process::Command;
 use std::env;
 
 const LOG_LINES: usize = 500; // Number of lines to show in the log viewer
+const EVENT_LINES: usize = 200; // Number of events to show in the events viewer
 const TABBAR_HEIGHT: f64 = 37.0;
 const CONTENT_PADDING: f64 = 10.0;
 const BORDER_PADDING: f64 = 3.0;
 const RESIZE_BORDER: f64 = 5.0; // Pixels from the edge that trigger resizing
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(5); // How long a button waits for its second click
 
 #[derive(Clone, Copy, PartialEq)]
 enum ResizeEdge {
@@ -58,6 +68,7 @@ enum ResizeEdge {
 #[derive(Clone, Copy, Eq, Hash, PartialEq)]
 enum Tab {
     Servers,
+    Events,
     Downtime,
     Alarms,
     Log,
@@ -67,6 +78,7 @@ impl std::fmt::Display for Tab {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match *self {
             Tab::Servers => write!(f, "Connections"),
+            Tab::Events => write!(f, "Events"),
             Tab::Downtime => write!(f, "Downtime"),
             Tab::Alarms => write!(f, "Alarms"),
             Tab::Log => write!(f, "Log"),
@@ -364,6 +376,13 @@ fn tab_content(
                 let colors = get_theme_colors();
                 s.width_full().flex_grow(1.0).background(colors.bg)
             }),
+        Tab::Events => container(
+                events_view(status_signal)
+            )
+            .style(|s| {
+                let colors = get_theme_colors();
+                s.width_full().flex_grow(1.0).background(colors.bg1)
+            }),
         Tab::Downtime => container(
                 downtime_view()
             )
@@ -410,7 +429,8 @@ fn downtime_view() -> impl IntoView {
             
             match sql_result {
                 Ok(packets) => {
-                    let downtime_records = process_downtime_packets(packets);
+                    let grace = seconds_before_downtime(&plc_view.get());
+                    let downtime_records = process_downtime_packets(packets, grace);
                     let records_vec: Vector<_> = downtime_records.into_iter().collect();
                     records_signal.set(records_vec);
                     error_signal.set(None);
@@ -597,10 +617,10 @@ fn downtime_view() -> impl IntoView {
                                             let _ = Command::new("notepad").arg(&path).spawn();
                                             crate::utils::log(&format!("Opened notepad with {}", path));
                                         }).map_err(|e| {
-                                            crate::utils::log(&format!("Failed to export downtime: {}", e))
+                                            crate::utils::log_error(&format!("Failed to export downtime: {}", e))
                                         });
                                     } else {
-                                        crate::utils::log("Failed to determine temp directory");
+                                        crate::utils::log_error("Failed to determine temp directory");
                                     }
                                 }).style(|_| button_style()),
                             ))
@@ -615,6 +635,92 @@ fn downtime_view() -> impl IntoView {
     )).style(|s| s.size_full().flex_col().background(get_theme_colors().bg1)).into_any()
 }
 
+// Reads the latest events and their snapshots on a blocking thread, so a slow database doesn't hold up the window
+fn load_recent_events(events_signal: RwSignal<Vec<(SqlDataPacket, Vec<EventSnapshot>)>>) {
+    let show = create_ext_action(Scope::new(), move |events| events_signal.set(events));
+    tokio::task::spawn_blocking(move || {
+        let events = connect_to_db()
+            .and_then(|conn| {
+                query_recent_packets(&conn, EVENT_LINES)?
+                    .into_iter()
+                    .map(|event| query_event_snapshots(&conn, event.id).map(|snapshots| (event, snapshots)))
+                    .collect()
+            })
+            .unwrap_or_else(|e| {
+                log_error(&format!("Failed to query recent events: {}", e));
+                Vec::new()
+            });
+        show(events);
+    });
+}
+
+fn events_view(status_signal: ReadSignal<ServerStatus>) -> impl IntoView {
+    let events_signal = RwSignal::new(Vec::<(SqlDataPacket, Vec<EventSnapshot>)>::new());
+
+    // Re-read the latest events only when a server has stored an event or snapshot, not for every status update
+    let stored_events = move || status_signal.get().server.iter().map(|s| s.stats.stored + s.stats.snapshots).sum::<u64>();
+    let last_stored = std::rc::Rc::new(std::cell::Cell::new(0));
+    let last_stored_update = last_stored.clone();
+    let stored = UpdaterEffect::new(stored_events, move |stored| {
+        if last_stored_update.replace(stored) != stored {
+            load_recent_events(events_signal);
+        }
+    });
+    last_stored.set(stored);
+    load_recent_events(events_signal);
+
+    let events_read = events_signal.read_only();
+    dyn_stack(
+        move || {
+            let v = events_read.get();
+            (0..v.len()).collect::<Vec<usize>>()
+        },
+        |idx| *idx,
+        move |idx| {
+            let events_read = events_read.clone();
+            label(move || {
+                events_read.get().get(idx).map(|(e, snapshots)| {
+                    // Show the decoded values if there was a schema, otherwise the raw DINTs
+                    let detail = if e.packet.values.is_empty() {
+                        format!("{:?}", e.packet.data)
+                    } else {
+                        format_values(&e.packet.values)
+                    };
+                    let mut line = format!("{} | {} | type {} code {} | {}",
+                        e.event_time(), e.plc, e.packet.data_type, e.packet.plc_packet_code, detail);
+                    // Each snapshot area on its own line under the event, as bytes if it has no schema
+                    for snapshot in snapshots {
+                        let detail = if snapshot.values.is_empty() {
+                            format!("{:?}", snapshot.data)
+                        } else {
+                            format_values(&snapshot.values)
+                        };
+                        line.push_str(&format!("\n    snapshot DB{}.{} | {}", snapshot.db, snapshot.start, detail));
+                    }
+                    line
+                }).unwrap_or_default()
+            })
+            .style(|s| {
+                let colors = get_theme_colors();
+                s.width_full()
+                    .font_size(13.0)
+                    .color(colors.fg)
+                    .background(colors.bg1)
+            })
+        },
+    )
+    .style(|s| {
+        let colors = get_theme_colors();
+        s.flex_col()
+            .width_full()
+            .gap(5.0)
+            .padding(CONTENT_PADDING)
+            .background(colors.bg1)
+    })
+    .scroll()
+    .style(|s| s.width_full().flex_grow(1.0))
+}
+
 fn alarms_view() -> impl IntoView {
     label(|| "Alarms view - coming soon!").style(|s| s.font_size(20.0))
 }
@@ -626,7 +732,7 @@ fn log_view(status_signal: ReadSignal<ServerStatus>) -> impl IntoView {
     UpdaterEffect::new(
         move || status_signal.get(),
         move |status_signal| {
-            let log_content = file_tail("log.txt", LOG_LINES)
+            let log_content = file_tail(&log_path(), LOG_LINES)
                 .unwrap_or_else(|_| String::from("Failed to read log file."));
             let lines: Vec<String> = log_content.lines().map(|l| l.to_string()).collect();
             log_lines_signal.set(lines);
@@ -670,7 +776,7 @@ fn tab_navigation_view(
     status_signal: ReadSignal<ServerStatus>, 
     command_tx: mpsc::UnboundedSender<ServerCommand>
 ) -> impl IntoView {
-    let tabs = vec![Tab::Servers, Tab::Downtime, Tab::Alarms, Tab::Log]
+    let tabs = vec![Tab::Servers, Tab::Events, Tab::Downtime, Tab::Alarms, Tab::Log]
         .into_iter()
         .collect::<Vector<Tab>>();
     let tabs = RwSignal::new(tabs);
@@ -681,6 +787,7 @@ fn tab_navigation_view(
 
     let tabs_bar = h_stack((
         tab_button(Tab::Servers, tabs.read_only(), active_tab),
+        tab_button(Tab::Events, tabs.read_only(), active_tab),
         tab_button(Tab::Downtime, tabs.read_only(), active_tab),
         tab_button(Tab::Alarms, tabs.read_only(), active_tab),
         tab_button(Tab::Log, tabs.read_only(), active_tab),
@@ -716,7 +823,6 @@ fn tab_navigation_view(
 
 fn server_view(
     server: ServerEntry,
-    current_index: usize,
     command_tx: mpsc::UnboundedSender<ServerCommand>,
     on_remove: impl Fn() + 'static + Clone
 ) -> impl IntoView {
@@ -724,21 +830,58 @@ fn server_view(
     let name = RwSignal::new(server.name.clone());
     let ip_address = RwSignal::new(server.ip_address.clone());
     let port = RwSignal::new(server.port.to_string());
-    let server_id = server.id.clone();
+    let server_id = server.id;
     let autostart = RwSignal::new(server.autostart);
+    let connection_mode = RwSignal::new(server.connection_mode);
+    let transport = RwSignal::new(server.transport);
+    let framing = RwSignal::new(server.framing);
+    let record_length = RwSignal::new(server.record_length.to_string());
+    let local_tsap = RwSignal::new(server.local_tsap.clone());
+    let remote_tsap = RwSignal::new(server.remote_tsap.clone());
+    let read_downtime_db = RwSignal::new(server.read_downtime_db);
+    let downtime_schema = RwSignal::new(server.downtime_schema.clone());
+    let rack = RwSignal::new(server.rack.to_string());
+    let slot = RwSignal::new(server.slot.to_string());
+    let poll_interval = RwSignal::new(server.poll_interval_ms.to_string());
+    let clock_check = RwSignal::new(server.clock_check_secs.to_string());
+    let max_clock_drift = RwSignal::new(server.max_clock_drift_ms.to_string());
+    let armed = RwSignal::new(None::<Armed>); // The button waiting for its second click, if any
 
     // Clone a bunch of server IDs to avoid move errors.
-    let server_id_1 = server_id.clone();
-    let server_id_2 = server_id.clone();
-    let server_id_3 = server_id.clone();
-    let server_id_4 = server_id.clone();
-    let server_id_5 = server_id.clone();
-    let server_id_6 = server_id.clone();
-    let server_id_7 = server_id.clone();
-    let server_id_save = server_id.clone();
+    let server_id_1 = server_id;
+    let server_id_2 = server_id;
+    let server_id_3 = server_id;
+    let server_id_4 = server_id;
+    let server_id_5 = server_id;
+    let server_id_6 = server_id;
+    let server_id_7 = server_id;
+    let server_id_config = server_id;
+    let server_id_loss = server_id;
+    let server_id_loss_style = server_id;
+    let server_id_skew = server_id;
+    let server_id_drift = server_id;
+    let server_id_set_clock = server_id;
+    let server_id_write = server_id;
+    let server_id_error = server_id;
+    let server_id_traffic = server_id;
+    let server_id_peers = server_id;
+    let server_id_errors = server_id;
+    let server_id_error_style = server_id;
+    let server_id_save = server_id;
 
     let start_command_tx = command_tx.clone();
     let stop_command_tx = command_tx.clone();
+    let set_clock_command_tx = command_tx.clone();
+    let write_command_tx = command_tx.clone();
+    let write_actions = server.write_actions.clone();
+    let no_write_actions = write_actions.is_empty();
+    let allow_set_clock = server.allow_set_clock;
+    // Rack and slot are used by every S7 connection, so they're shown whenever one is configured
+    let has_s7_config = allow_set_clock || !no_write_actions || !server.snapshot_triggers.is_empty();
+    let uses_s7 = move || has_s7_config
+        || transport.get() == Transport::S7Poll
+        || read_downtime_db.get()
+        || clock_check.get().trim().parse::<u64>().is_ok_and(|secs| secs > 0);
 
         // Get an approximate width for the IP address input
     let sample = "255.255.255.255";
@@ -769,7 +912,7 @@ fn server_view(
                         if s.peer_ip == [0; 16] {
                             "".to_string()
                         } else {
-                            status.get_ip_string(s.idx)
+                            status.get_ip_string(&s.server_id)
                         }
                     })
                     .unwrap_or_else(|| "b.o.r.k".to_string())
@@ -777,9 +920,32 @@ fn server_view(
                 let colors = get_theme_colors();
                 s.font_size(12.0).color(colors.fg)
             }),
+            // Every connection, when there can be more than one (e.g. a redundant pair)
+            label(move || {
+                status_signal.get().server.iter()
+                    .find(|s| s.matches_server_id(&server_id_peers))
+                    .map(|s| s.peers.iter()
+                        .filter(|p| p.is_used())
+                        .map(|p| {
+                            let state = if !p.connected {
+                                "disconnected"
+                            } else if p.alive {
+                                "alive"
+                            } else {
+                                "connected"
+                            };
+                            format!("{}: {}, {} packets", p.ip_addr(), state, p.packets)
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n"))
+                    .unwrap_or_default()
+            }).style(move |s| {
+                let colors = get_theme_colors();
+                s.font_size(12.0).color(colors.fg)
+            }),
             label(move || "").style(|s| s.font_size(6.0).flex_grow(1.0)), // Spacer
             label(move || {
-                let server_id = server_id.clone();
+                let server_id = server_id;
                 status_signal.get().server.iter()
                     .find(|s| s.matches_server_id(&server_id))
                     .map(|s| {
@@ -799,22 +965,119 @@ fn server_view(
                                     .font_style(floem::text::Style::Italic)
                                     .color(colors.fg)
                 }),
+            label(move || {
+                status_signal.get().server.iter()
+                    .find(|s| s.matches_server_id(&server_id_config))
+                    .and_then(|s| s.plc_config)
+                    .map(|c| format!("PLC config: downtime DB {}, {} s before downtime", c.downtime_db, c.seconds_before_downtime))
+                    .unwrap_or_else(|| "No config packet received".to_string())
+            }).style(move |s| {
+                let colors = get_theme_colors();
+                s.font_size(12.0).color(colors.fg)
+            }),
+            label(move || {
+                status_signal.get().server.iter()
+                    .find(|s| s.matches_server_id(&server_id_loss))
+                    .map(|s| format!("Lost packets: {} | Duplicates dropped: {}", s.lost_packets, s.duplicate_packets))
+                    .unwrap_or_default()
+            }).style(move |s| {
+                let colors = get_theme_colors();
+                let has_loss = status_signal.get().server.iter()
+                    .find(|s| s.matches_server_id(&server_id_loss_style))
+                    .map(|s| s.lost_packets > 0)
+                    .unwrap_or(false);
+                s.font_size(12.0).color(if has_loss { colors.red } else { colors.fg })
+            }),
+            label(move || {
+                status_signal.get().server.iter()
+                    .find(|s| s.matches_server_id(&server_id_skew))
+                    .and_then(|s| s.clock_skew_ms)
+                    .map(|skew| format!("PLC clock skew: {:.1} s", skew as f64 / 1000.0))
+                    .unwrap_or_default()
+            }).style(move |s| {
+                let colors = get_theme_colors();
+                s.font_size(12.0).color(colors.fg)
+            }),
+            label(move || {
+                status_signal.get().server.iter()
+                    .find(|s| s.matches_server_id(&server_id_drift))
+                    .and_then(|s| s.clock_drift_ms)
+                    .map(|drift| format!("PLC clock drift (read over S7): {:+.1} s", drift as f64 / 1000.0))
+                    .unwrap_or_default()
+            }).style(move |s| {
+                let colors = get_theme_colors();
+                let too_far = status_signal.get().server.iter()
+                    .find(|s| s.matches_server_id(&server_id_drift))
+                    .and_then(|s| s.clock_drift_ms)
+                    .is_some_and(|drift| drift.unsigned_abs() > max_clock_drift.get().parse::<u64>().unwrap_or(DEFAULT_MAX_CLOCK_DRIFT_MS));
+                s.font_size(12.0).color(if too_far { colors.red } else { colors.fg })
+            }),
+            label(move || {
+                let now = Local::now().timestamp_millis() as u64;
+                status_signal.get().server.iter()
+                    .find(|s| s.matches_server_id(&server_id_traffic))
+                    .map(|s| format!("Packets: {} ({}/min) | {:.1} kB | Keepalives: {}",
+                        s.stats.packets, s.stats.packets_per_min(now), s.stats.bytes as f64 / 1024.0, s.stats.keepalives))
+                    .unwrap_or_default()
+            }).style(move |s| {
+                let colors = get_theme_colors();
+                s.font_size(12.0).color(colors.fg)
+            }),
+            label(move || {
+                let now = Local::now().timestamp_millis() as u64;
+                status_signal.get().server.iter()
+                    .find(|s| s.matches_server_id(&server_id_errors))
+                    .map(|s| {
+                        let uptime = if s.stats.connected_since == 0 {
+                            "not connected".to_string()
+                        } else {
+                            format_seconds_to_duration((now.saturating_sub(s.stats.connected_since) / 1000) as i64)
+                        };
+                        format!("Parse errors: {} | DB errors: {} | Reconnects: {} | Uptime: {}",
+                            s.stats.parse_errors, s.stats.db_errors, s.stats.reconnects(), uptime)
+                    })
+                    .unwrap_or_default()
+            }).style(move |s| {
+                let colors = get_theme_colors();
+                s.font_size(12.0).color(colors.fg)
+            }),
+            label(move || {
+                status_signal.get().server.iter()
+                    .find(|s| s.matches_server_id(&server_id_error))
+                    .filter(|s| s.bind_failed)
+                    .map(|s| format!("Not listening: {}", s.get_last_error()))
+                    .unwrap_or_default()
+            }).style(move |s| {
+                let colors = get_theme_colors();
+                let bind_failed = status_signal.get().server.iter()
+                    .find(|s| s.matches_server_id(&server_id_error_style))
+                    .map(|s| s.bind_failed)
+                    .unwrap_or(false);
+                s.font_size(12.0).color(colors.red)
+                    .apply_if(!bind_failed, |s| s.hide())
+            }),
             h_stack((
                 label(move || {
-                    let server_id = server_id_2.clone();
+                    let server_id = server_id_2;
                     let status = status_signal.get();
                     println!("Looking for server ID: {:?}", server_id);
                     println!("Available server statuses: {:?}", status.server.iter().map(|s| &s.server_id).collect::<Vec<_>>());
                     status_signal.get().server.iter()
                         .find(|s| s.matches_server_id(&server_id))
-                        .map(|s| if s.is_running { "Running".to_string() } else { "Stopped".to_string() })
+                        .map(|s| if s.bind_failed {
+                            "Bind failed".to_string()
+                        } else if s.is_running {
+                            "Running".to_string()
+                        } else {
+                            "Stopped".to_string()
+                        })
                         .unwrap_or_else(|| "Unknown".to_string())
                 }).style(move |s| {
                     let colors = get_theme_colors();
-                    let server_id = server_id_3.clone();
+                    let server_id = server_id_3;
                     let is_running = status_signal.get().server.iter()
                         .find(|s| s.matches_server_id(&server_id))
-                        .map(|s| s.is_running)
+                        .map(|s| s.is_running && !s.bind_failed)
                         .unwrap_or(false);
                     s.color(if is_running { colors.green } else { colors.red })
                         .background(colors.bg)
@@ -822,14 +1085,14 @@ fn server_view(
                         .padding(5.0)
                 }),
                 label(move || {
-                    let server_id = server_id_4.clone();
+                    let server_id = server_id_4;
                     status_signal.get().server.iter()
                         .find(|s| s.matches_server_id(&server_id))
                         .map(|s| if s.is_connected { "Connected".to_string() } else { "Disconnected".to_string() })
                         .unwrap_or_else(|| "Disconnected".to_string())
                 }).style(move |s| {
                     let colors = get_theme_colors();
-                    let server_id = server_id_5.clone();
+                    let server_id = server_id_5;
                     let is_connected = status_signal.get().server.iter()
                         .find(|s| s.matches_server_id(&server_id))
                         .map(|s| s.is_connected)
@@ -840,14 +1103,14 @@ fn server_view(
                         .padding(5.0)
                 }),
                 label(move || {
-                    let server_id = server_id_6.clone();
+                    let server_id = server_id_6;
                     status_signal.get().server.iter()
                         .find(|s| s.matches_server_id(&server_id))
                         .map(|s| if s.is_alive { "Alive".to_string() } else { "Not Alive".to_string() })
                         .unwrap_or_else(|| "Not Alive".to_string())
                 }).style(move |s| {
                     let colors = get_theme_colors();
-                    let server_id = server_id_7.clone();
+                    let server_id = server_id_7;
                     let is_alive = status_signal.get().server.iter()
                         .find(|s| s.matches_server_id(&server_id))
                         .map(|s| s.is_alive)
@@ -862,7 +1125,78 @@ fn server_view(
         // Input fields for IP and Port
         v_stack((
             h_stack((
-                label(||"Local IP Address on PLC Network"),
+                label(||"Transport"),
+                button(label(move || match transport.get() {
+                    Transport::Tcp => "TCP",
+                    Transport::Udp => "UDP",
+                    Transport::IsoOnTcp => "ISO-on-TCP",
+                    Transport::S7Poll => "S7 polling",
+                })).action(move || {
+                    armed.set(None);
+                    transport.update(|t| *t = match *t {
+                        Transport::Tcp => Transport::Udp,
+                        Transport::Udp => Transport::IsoOnTcp,
+                        Transport::IsoOnTcp => Transport::S7Poll,
+                        Transport::S7Poll => Transport::Tcp,
+                    });
+                }).style(|_| button_style().min_width(150.0)),
+            )).style(move |s| {
+                let colors = get_theme_colors();
+                s.justify_end().gap(CONTENT_PADDING).items_center().color(colors.fg)
+            }),
+            h_stack((
+                label(||"Framing"),
+                button(label(move || match framing.get() {
+                    FramingMode::Raw => "One packet per read",
+                    FramingMode::LengthPrefixed => "Length prefixed",
+                    FramingMode::FixedRecord => "Fixed length records",
+                })).action(move || {
+                    armed.set(None);
+                    framing.update(|f| *f = match *f {
+                        FramingMode::Raw => FramingMode::LengthPrefixed,
+                        FramingMode::LengthPrefixed => FramingMode::FixedRecord,
+                        FramingMode::FixedRecord => FramingMode::Raw,
+                    });
+                }).style(|_| button_style().min_width(150.0)),
+            )).style(move |s| {
+                let colors = get_theme_colors();
+                // ISO-on-TCP and UDP keep the PLC's packet boundaries, S7 polling has no packets
+                s.justify_end().gap(CONTENT_PADDING).items_center().color(colors.fg)
+                    .apply_if(transport.get() != Transport::Tcp, |s| s.hide())
+            }),
+            h_stack((
+                label(||"Record Length (bytes)"),
+                text_input(record_length).style(move |_| input_style().min_width(min_ip_width)),
+            )).style(move |s| {
+                let colors = get_theme_colors();
+                s.justify_end().gap(CONTENT_PADDING).items_center().color(colors.fg)
+                    .apply_if(transport.get() != Transport::Tcp || framing.get() != FramingMode::FixedRecord, |s| s.hide())
+            }),
+            h_stack((
+                label(||"Connection"),
+                button(label(move || match connection_mode.get() {
+                    ConnectionMode::Listen => "PLC connects to us",
+                    ConnectionMode::Connect => "We connect to PLC",
+                })).action(move || {
+                    armed.set(None);
+                    connection_mode.update(|m| *m = match *m {
+                        ConnectionMode::Listen => ConnectionMode::Connect,
+                        ConnectionMode::Connect => ConnectionMode::Listen,
+                    });
+                }).style(|_| button_style().min_width(150.0)),
+            )).style(move |s| {
+                let colors = get_theme_colors();
+                // UDP has no connection to open, and S7 polling always connects to the PLC
+                s.justify_end().gap(CONTENT_PADDING).items_center().color(colors.fg)
+                    .apply_if(matches!(transport.get(), Transport::Udp | Transport::S7Poll), |s| s.hide())
+            }),
+            h_stack((
+                label(move || match (transport.get(), connection_mode.get()) {
+                    (Transport::Udp, _) => "Local IP Address on PLC Network",
+                    (Transport::S7Poll, _) => "PLC IP Address",
+                    (_, ConnectionMode::Listen) => "Local IP Address on PLC Network",
+                    (_, ConnectionMode::Connect) => "PLC IP Address",
+                }),
                 text_input(ip_address).style(move |_| input_style().min_width(min_ip_width)),
             )).style(move |s| {
                 let colors = get_theme_colors();
@@ -874,6 +1208,62 @@ fn server_view(
             )).style(move |s| {
                 let colors = get_theme_colors();
                 s.justify_end().gap(CONTENT_PADDING).items_center().color(colors.fg)
+                    .apply_if(transport.get() == Transport::S7Poll, |s| s.hide())
+            }),
+            h_stack((
+                label(||"Rack"),
+                text_input(rack).style(move |_| input_style().min_width(min_ip_width)),
+            )).style(move |s| {
+                let colors = get_theme_colors();
+                s.justify_end().gap(CONTENT_PADDING).items_center().color(colors.fg)
+                    .apply_if(!uses_s7(), |s| s.hide())
+            }),
+            h_stack((
+                label(||"Slot"),
+                text_input(slot).style(move |_| input_style().min_width(min_ip_width)),
+            )).style(move |s| {
+                let colors = get_theme_colors();
+                s.justify_end().gap(CONTENT_PADDING).items_center().color(colors.fg)
+                    .apply_if(!uses_s7(), |s| s.hide())
+            }),
+            h_stack((
+                label(||"Poll Interval (ms)"),
+                text_input(poll_interval).style(move |_| input_style().min_width(min_ip_width)),
+            )).style(move |s| {
+                let colors = get_theme_colors();
+                s.justify_end().gap(CONTENT_PADDING).items_center().color(colors.fg)
+                    .apply_if(transport.get() != Transport::S7Poll, |s| s.hide())
+            }),
+            h_stack((
+                label(||"Clock Check (s)"),
+                text_input(clock_check).style(move |_| input_style().min_width(min_ip_width)),
+            )).style(move |s| {
+                let colors = get_theme_colors();
+                s.justify_end().gap(CONTENT_PADDING).items_center().color(colors.fg)
+            }),
+            h_stack((
+                label(||"Max Clock Drift (ms)"),
+                text_input(max_clock_drift).style(move |_| input_style().min_width(min_ip_width)),
+            )).style(move |s| {
+                let colors = get_theme_colors();
+                s.justify_end().gap(CONTENT_PADDING).items_center().color(colors.fg)
+                    .apply_if(clock_check.get().trim() == "0", |s| s.hide())
+            }),
+            h_stack((
+                label(||"Local TSAP"),
+                text_input(local_tsap).style(move |_| input_style().min_width(min_ip_width)),
+            )).style(move |s| {
+                let colors = get_theme_colors();
+                s.justify_end().gap(CONTENT_PADDING).items_center().color(colors.fg)
+                    .apply_if(transport.get() != Transport::IsoOnTcp, |s| s.hide())
+            }),
+            h_stack((
+                label(||"PLC TSAP"),
+                text_input(remote_tsap).style(move |_| input_style().min_width(min_ip_width)),
+            )).style(move |s| {
+                let colors = get_theme_colors();
+                s.justify_end().gap(CONTENT_PADDING).items_center().color(colors.fg)
+                    .apply_if(transport.get() != Transport::IsoOnTcp, |s| s.hide())
             }),
             h_stack((
                 label(||"Auto start").style(move |s| {
@@ -883,48 +1273,137 @@ fn server_view(
                 checkbox(move || autostart.get())
                 .style(|_| checkbox_style())
                 .on_update(move |is_checked| {
+                    armed.set(None);
                     autostart.set(is_checked);
                 })
             )).style(|s| s.items_center().gap(CONTENT_PADDING)),
+            h_stack((
+                label(||"Read downtime DB").style(move |s| {
+                    let colors = get_theme_colors();
+                    s.color(colors.fg)
+                }),
+                checkbox(move || read_downtime_db.get())
+                .style(|_| checkbox_style())
+                .on_update(move |is_checked| {
+                    armed.set(None);
+                    read_downtime_db.set(is_checked);
+                })
+            )).style(|s| s.items_center().gap(CONTENT_PADDING)),
+            h_stack((
+                label(||"Downtime Schema"),
+                text_input(downtime_schema).style(move |_| input_style().min_width(min_ip_width)),
+            )).style(move |s| {
+                let colors = get_theme_colors();
+                // Empty reads the 5 DInts
+                s.justify_end().gap(CONTENT_PADDING).items_center().color(colors.fg)
+                    .apply_if(!read_downtime_db.get(), |s| s.hide())
+            }),
             button("Save")
                 .action(move || {
+                    armed.set(None);
                     let new_ip = ip_address.get().clone();
                     let new_port = port.get().parse::<u16>().unwrap_or(0);
                     let new_name = name.get().clone();
                     let new_autostart = autostart.get();
-
-                    unsafe {
-                        // Find and update the server by ID
-                        if let Some(server) = SERVER_CONFIG.server.iter_mut().find(|s| s.id == server_id_save) {
-                            server.ip_address = new_ip.clone();
-                            server.port = new_port;
-                            server.name = new_name.clone();
-                            server.autostart = new_autostart;
-                            
-                            if let Err(e) = crate::xmlhandling::save_config("config.xml") {
-                                log(&format!("Failed to save config: {}", e));
-                            } else {
-                                log("Config saved.");
-                            }
-                            if DEBUG { println!("Saved server {:?}: {}:{}", server_id_save, new_ip, new_port); }
+                    let new_connection_mode = connection_mode.get();
+                    let new_transport = transport.get();
+                    let new_framing = framing.get();
+                    let new_record_length = record_length.get().parse::<usize>().unwrap_or(0);
+                    let new_local_tsap = local_tsap.get();
+                    let new_remote_tsap = remote_tsap.get();
+                    let new_read_downtime_db = read_downtime_db.get();
+                    let new_downtime_schema = downtime_schema.get().trim().to_string();
+                    let new_rack = rack.get().parse::<u16>().unwrap_or(0);
+                    let new_slot = slot.get().parse::<u16>().unwrap_or(S7_DEFAULT_SLOT);
+                    let new_poll_interval = poll_interval.get().parse::<u64>().unwrap_or(POLL_INTERVAL_MS);
+                    let new_clock_check = clock_check.get().parse::<u64>().unwrap_or(0);
+                    let new_max_clock_drift = max_clock_drift.get().parse::<u64>().unwrap_or(DEFAULT_MAX_CLOCK_DRIFT_MS);
+
+                    // Find and update the server by ID
+                    let found = SERVER_CONFIG.update(|config| {
+                        let Some(server) = config.server.iter_mut().find(|s| s.id == server_id_save) else {
+                            return false;
+                        };
+                        server.ip_address = new_ip.clone();
+                        server.port = new_port;
+                        server.name = new_name.clone();
+                        server.autostart = new_autostart;
+                        server.connection_mode = new_connection_mode;
+                        server.transport = new_transport;
+                        server.framing = new_framing;
+                        server.record_length = new_record_length;
+                        server.local_tsap = new_local_tsap.clone();
+                        server.remote_tsap = new_remote_tsap.clone();
+                        server.read_downtime_db = new_read_downtime_db;
+                        server.downtime_schema = new_downtime_schema.clone();
+                        server.rack = new_rack;
+                        server.slot = new_slot;
+                        server.poll_interval_ms = new_poll_interval;
+                        server.clock_check_secs = new_clock_check;
+                        server.max_clock_drift_ms = new_max_clock_drift;
+                        true
+                    });
+                    if found {
+                        if let Err(e) = crate::xmlhandling::save_config(&config_path()) {
+                            log_error(&format!("Failed to save config: {}", e));
+                        } else {
+                            log("Config saved.");
                         }
+                        if debug_logging() { println!("Saved server {:?}: {}:{}", server_id_save, new_ip, new_port); }
                     }
                 }).style(|_| button_style().width(100.0).height(30.0)),
         )).style(|s| s.gap(5.0).items_end()),
+        // Write actions from config.xml, if the server has any
+        dyn_stack(
+            move || write_actions.clone(),
+            |action| action.clone(),
+            move |action| write_action_view(action, server_id_write, write_command_tx.clone(), status_signal, armed),
+        ).style(move |s| s.flex_col().gap(5.0).items_end()
+            .apply_if(no_write_actions, |s| s.hide())),
         v_stack((
             {
                 button("Start Server").action(move || {
-                    let _ = start_command_tx.send(ServerCommand::Start(current_index));
+                    armed.set(None);
+                    let _ = start_command_tx.send(ServerCommand::Start(server_id));
                 }).style(|_| button_style().height_full())
             },
             {
                 button("Stop Server").action(move || {
-                    let _ = stop_command_tx.send(ServerCommand::Stop(current_index));
+                    armed.set(None);
+                    let _ = stop_command_tx.send(ServerCommand::Stop(server_id));
                 }).style(|_| button_style().height_full())
             },
+            {
+                // Changing the PLC's clock moves every timestamp it makes, so it takes a second click,
+                // and the server has to allow it in config.xml
+                button(label(move || if is_armed(armed, SET_CLOCK_BUTTON) { "Write PC Time to PLC?" } else { SET_CLOCK_BUTTON }))
+                    .action(move || {
+                        if !is_armed(armed, SET_CLOCK_BUTTON) {
+                            arm(armed, SET_CLOCK_BUTTON);
+                            return;
+                        }
+                        armed.set(None);
+                        match connected_plc(&status_signal.get(), &server_id_set_clock) {
+                            Some(ip) => { let _ = set_clock_command_tx.send(ServerCommand::SetPlcClock(server_id_set_clock, ip)); }
+                            None => log_warn("Can't set the PLC clock, the PLC isn't connected."),
+                        }
+                    }).style(move |_| {
+                        let colors = get_theme_colors();
+                        button_style().height_full()
+                            .apply_if(is_armed(armed, SET_CLOCK_BUTTON), |s| s.color(colors.red))
+                            .apply_if(!allow_set_clock, |s| s.hide())
+                    })
+            },
+            {
+                button("Cancel").action(move || {
+                    armed.set(None);
+                }).style(move |_| button_style().height_full()
+                    .apply_if(!is_armed(armed, SET_CLOCK_BUTTON), |s| s.hide()))
+            },
             {
                 let on_remove = on_remove.clone();
                 button("Remove Server").action(move || {
+                    armed.set(None);
                     on_remove();
                 }).style(move |_| button_style().height_full())
             },
@@ -940,6 +1419,87 @@ fn server_view(
         })
 }
 
+const SET_CLOCK_BUTTON: &str = "Set PLC Clock";
+
+// A button that takes a second click. `at` tells one click on it from the next, so an old
+// timeout can't disarm it.
+#[derive(Clone, PartialEq)]
+struct Armed {
+    button: String,
+    at: std::time::Instant,
+}
+
+// Waits for the second click on `button`. Any other button on the server, or CONFIRM_TIMEOUT, disarms it.
+fn arm(armed: RwSignal<Option<Armed>>, button: &str) {
+    let arming = Armed { button: button.to_string(), at: std::time::Instant::now() };
+    armed.set(Some(arming.clone()));
+    exec_after(CONFIRM_TIMEOUT, move |_| {
+        if armed.get_untracked().as_ref() == Some(&arming) {
+            armed.set(None);
+        }
+    });
+}
+
+fn is_armed(armed: RwSignal<Option<Armed>>, button: &str) -> bool {
+    armed.get().is_some_and(|a| a.button == button)
+}
+
+// The PLC that S7 requests go to: the first one connected to the server
+fn connected_plc(status: &ServerStatus, server_id: &ServerId) -> Option<std::net::IpAddr> {
+    status.server.iter()
+        .find(|s| s.matches_server_id(server_id))
+        .and_then(|s| s.peers.iter().find(|p| p.connected).map(|p| p.ip_addr()))
+}
+
+// One write action: the value, if the operator enters it, and a Write button that takes a second click
+fn write_action_view(
+    action: WriteAction,
+    server_id: ServerId,
+    command_tx: mpsc::UnboundedSender<ServerCommand>,
+    status_signal: ReadSignal<ServerStatus>,
+    armed: RwSignal<Option<Armed>>,
+) -> impl IntoView {
+    let value = RwSignal::new(action.value.clone());
+    let title = format!("{} ({})", action.name, action.address());
+    let operator_value = action.operator_value;
+    let action_name = action.name;
+    let write_button = format!("Write {}", action_name); // Which of the server's buttons is armed
+    let confirm = {
+        let write_button = write_button.clone();
+        move || is_armed(armed, &write_button)
+    };
+    let (confirm_action, confirm_style, confirm_cancel) = (confirm.clone(), confirm.clone(), confirm.clone());
+
+    h_stack((
+        label(move || title.clone()),
+        text_input(value).style(move |_| input_style().min_width(80.0)
+            .apply_if(!operator_value, |s| s.hide())),
+        button(label(move || if confirm() { format!("Write {}?", value.get()) } else { "Write".to_string() }))
+            .action(move || {
+                if !confirm_action() {
+                    arm(armed, &write_button);
+                    return;
+                }
+                armed.set(None);
+                match connected_plc(&status_signal.get(), &server_id) {
+                    Some(ip) => { let _ = command_tx.send(ServerCommand::WriteToPlc(server_id, ip, action_name.clone(), value.get())); }
+                    None => log_warn(&format!("Can't run {}, the PLC isn't connected.", action_name)),
+                }
+            }).style(move |_| {
+                let colors = get_theme_colors();
+                button_style().min_width(80.0)
+                    .apply_if(confirm_style(), |s| s.color(colors.red))
+            }),
+        button("Cancel").action(move || {
+            armed.set(None);
+        }).style(move |_| button_style()
+            .apply_if(!confirm_cancel(), |s| s.hide())),
+    )).style(move |s| {
+        let colors = get_theme_colors();
+        s.justify_end().gap(CONTENT_PADDING).items_center().color(colors.fg)
+    })
+}
+
 // Updated server_stack function with proper keying
 fn server_stack(
     _status_signal: ReadSignal<ServerStatus>, 
@@ -950,49 +1510,44 @@ fn server_stack(
     let dyn_stack_command_tx = command_tx.clone();
     let remove_command_tx = command_tx.clone();
 
-    // Only sync when the number of servers changes, not on every status update
-    UpdaterEffect::new(
-        move || unsafe {SERVER_CONFIG.server.clone()},
-        move |current_config| {
-            server_config_signal.set(current_config);
-        },
-    );
+    // Follow changes made anywhere, e.g. the server manager adding or removing a server.
+    // Waits for the next change in the background, then hands it to the UI thread.
+    fn follow_config(
+        mut config_rx: tokio::sync::watch::Receiver<ServerConfig>,
+        server_config_signal: RwSignal<Vec<ServerEntry>>,
+    ) {
+        let show = create_ext_action(Scope::new(), move |(servers, config_rx)| {
+            server_config_signal.set(servers);
+            follow_config(config_rx, server_config_signal);
+        });
+        tokio::spawn(async move {
+            if config_rx.changed().await.is_ok() {
+                let servers = config_rx.borrow_and_update().server.clone();
+                show((servers, config_rx));
+            }
+        });
+    }
+    follow_config(SERVER_CONFIG.subscribe(), server_config_signal);
     
     v_stack((
         /*container(*/scroll(
             dyn_stack(
                 // Use server entries with their IDs as the data source
                 move || server_config_signal.get(),
-                // Key by server ID instead of index
-                |server| server.id.clone(),
+                // Key by the whole entry, so a row is rebuilt when its settings change (e.g. config.xml reloaded)
+                |server| server.clone(),
                 move |server| {
-                    // Find the current index of this server in the config
-                    let server_id = server.id.clone();
-                    let current_index = server_config_signal.get()
-                        .iter()
-                        .position(|s| s.id == server_id)
-                        .unwrap_or(0);
-                    
                     let server_command_tx = dyn_stack_command_tx.clone();
                     let remove_server_command_tx = remove_command_tx.clone();
-                    let server_id_for_removal = server_id.clone();
+                    let server_id_for_removal = server.id;
                     
                     server_view(
                         server.clone(), 
-                        current_index,
                         //status_signal, 
                         server_command_tx, 
                         move || {
-                            // Remove by server ID, not index
-                            let config = server_config_signal.get();
-                            if let Some(index_to_remove) = config.iter().position(|s| s.id == server_id_for_removal) {
-                                let _ = remove_server_command_tx.send(ServerCommand::RemoveServer(index_to_remove));
-                                
-                                // Update the signal immediately
-                                server_config_signal.update(|config| {
-                                    config.retain(|s| s.id != server_id_for_removal);
-                                });
-                            }
+                            // The list updates when the manager has removed it from the config
+                            let _ = remove_server_command_tx.send(ServerCommand::RemoveServer(server_id_for_removal));
                         }
                     )
                     .style(move |s| {
@@ -1011,17 +1566,8 @@ fn server_stack(
             label(||"").style(|s| s.width_full()), //Spacer
             button("Add Connection")
             .action(move || {
-                let new_server = ServerEntry {
-                    id: generate_server_id(),
-                    name: "New Server".to_string(),
-                    ip_address: "0.0.0.0".to_string(),
-                    port: 2000,
-                    autostart: false,
-                };
-                let _ = command_tx.send(ServerCommand::AddServer(new_server.clone()));
-                server_config_signal.update(|config| {
-                    config.push(new_server);
-                });
+                // The list updates when the manager has added it to the config
+                let _ = command_tx.send(ServerCommand::AddServer(Box::default()));
             }).style(|_| button_style().height(30.0)),
             label(||"").style(|s| s.width_full()), //Spacer
         )).style(|s| s.items_start())//,
@@ -1103,7 +1649,7 @@ fn custom_window_menu() -> impl IntoView {
 // Create a signal for the server configuration (for reactive UI)
 fn create_server_config_signal() -> RwSignal<Vec<ServerEntry>> {//ReadSignal<Vec<ServerEntry>>, WriteSignal<Vec<ServerEntry>>) {
     // Initialize with the current config
-    let initial_config = unsafe { SERVER_CONFIG.server.clone() };
+    let initial_config = SERVER_CONFIG.servers();
     RwSignal::new(initial_config)
 }
 
@@ -1124,7 +1670,7 @@ pub fn app_view(rx: Receiver<ServerStatusInfo>, command_tx: tokio::sync::mpsc::U
             while let Ok(status) = rx_guard.try_recv() {
                 set_status_signal.update(|statuses| {
                     // Update existing status or add new one
-                    if let Some(existing) = statuses.server.iter_mut().find(|s| s.idx == status.idx) {
+                    if let Some(existing) = statuses.server.iter_mut().find(|s| s.matches_server_id(&status.server_id)) {
                         *existing = status;
                     } else {
                         statuses.server.push(status);
@@ -1,18 +1,101 @@
 use chrono::Local;
 use std::fs::OpenOptions;
 use std::io::Write;
-use std::time::{SystemTime, UNIX_EPOCH};
-use crate::constants::DEBUG;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::RwLock;
+use once_cell::sync::Lazy;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::constants::{DEBUG, LOG_PATH};
+
+// log() is Info, log_warn() and log_error() are the other levels.
+// Debug messages are the ones only logged when debug_logging() is true.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warning,
+    Info,
+    Debug,
+}
+
+impl LogLevel {
+    pub fn parse(level: &str) -> Result<Self, &'static str> {
+        match level.to_ascii_lowercase().as_str() {
+            "error" => Ok(LogLevel::Error),
+            "warning" | "warn" => Ok(LogLevel::Warning),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            _ => Err("Log level must be error, warning, info or debug"),
+        }
+    }
+
+}
+
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(if DEBUG { LogLevel::Debug as u8 } else { LogLevel::Info as u8 });
+static LOG_TO_CONSOLE: AtomicBool = AtomicBool::new(DEBUG);
+static LOG_FILE: Lazy<RwLock<String>> = Lazy::new(|| RwLock::new(LOG_PATH.to_string()));
+
+// Every message logged after this goes to the new file
+pub fn set_log_path(path: &str) {
+    *LOG_FILE.write().unwrap() = path.to_string();
+}
+
+pub fn log_path() -> String {
+    LOG_FILE.read().unwrap().clone()
+}
+
+pub fn set_log_level(level: LogLevel) {
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn log_level() -> LogLevel {
+    match LOG_LEVEL.load(Ordering::Relaxed) {
+        0 => LogLevel::Error,
+        1 => LogLevel::Warning,
+        2 => LogLevel::Info,
+        _ => LogLevel::Debug,
+    }
+}
+
+pub fn debug_logging() -> bool {
+    log_level() >= LogLevel::Debug
+}
+
+// Also print log messages, e.g. when running headless
+pub fn set_log_to_console(enabled: bool) {
+    LOG_TO_CONSOLE.store(enabled, Ordering::Relaxed);
+}
 
 pub fn log(message: &str) {
+    write_log(LogLevel::Info, message);
+}
+
+// Something went wrong but the application carries on, e.g. a PLC that can't be reached
+pub fn log_warn(message: &str) {
+    write_log(LogLevel::Warning, message);
+}
+
+// Something that needs fixing, e.g. data that couldn't be stored
+pub fn log_error(message: &str) {
+    write_log(LogLevel::Error, message);
+}
+
+fn write_log(level: LogLevel, message: &str) {
+    if level > log_level() {
+        return;
+    }
+    let message = match level {
+        LogLevel::Error => format!("ERROR: {}", message),
+        LogLevel::Warning => format!("WARNING: {}", message),
+        _ => message.to_string(),
+    };
     let timestamp = Local::now().format("%Y-%m-%d %H:%M:%S");
     // Log to console
-    if DEBUG { println!("[{}] {}", timestamp, message); }
+    if LOG_TO_CONSOLE.load(Ordering::Relaxed) { println!("[{}] {}", timestamp, message); }
     // Append to log file
     if let Ok(mut file) = OpenOptions::new()
         .create(true)
         .append(true)
-        .open("log.txt")
+        .open(log_path())
     {
         let _ = file.write_all(format!("[{}] {}\n", timestamp, message).as_bytes());
     } else {
@@ -26,16 +109,47 @@ pub fn widestring(s: &str) -> Vec<u16> {
     OsStr::new(s).encode_wide().chain(std::iter::once(0)).collect()
 }
 
-pub fn string_to_fixed_array(s: &str) -> [u8; 32] {
-    let mut array = [0u8; 32];
+pub fn string_to_fixed_array<const N: usize>(s: &str) -> [u8; N] {
+    let mut array = [0u8; N];
     let bytes = s.as_bytes();
-    let len = std::cmp::min(bytes.len(), 32);
+    let len = std::cmp::min(bytes.len(), N);
     array[..len].copy_from_slice(&bytes[..len]);
     array
 }
 
-pub fn fixed_array_to_string(array: &[u8; 32]) -> String {
+pub fn fixed_array_to_string<const N: usize>(array: &[u8; N]) -> String {
     // Find the first null byte or use the full array
-    let end = array.iter().position(|&b| b == 0).unwrap_or(32);
+    let end = array.iter().position(|&b| b == 0).unwrap_or(N);
     String::from_utf8_lossy(&array[..end]).to_string()
-}
\ No newline at end of file
+}
+
+// Who is logged in to Windows, for audit records
+pub fn current_user() -> String {
+    std::env::var("USERNAME")
+        .or_else(|_| std::env::var("USER"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+// Delay between retries, doubling each time up to `max`.
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    next: Duration,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Backoff { min, max, next: min }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.max);
+        delay
+    }
+
+    // Call after a success so the next failure retries quickly
+    pub fn reset(&mut self) {
+        self.next = self.min;
+    }
+}
@@ -1,10 +1,16 @@
-use base64::{Engine as _, engine::general_purpose, engine::general_purpose::STANDARD};
 use std::fs::File;
 use std::fs;
 use std::io::Write;
 use quick_xml::se::Serializer;
-use serde::{Serialize, Deserialize, Deserializer};
-use crate::comms_tcon::{ServerConfig, SERVER_CONFIG, };
+use serde::Serialize;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+use crate::comms_tcon::{ServerCommand, ServerConfig};
+use crate::config_store::SERVER_CONFIG;
+use crate::constants::CONFIG_POLL_MS;
+use crate::event_data::downtime_schema;
+use crate::payload_schema::SchemaRegistry;
+use crate::utils::{log, log_error};
 use quick_xml::de::{from_str,};
 
 pub fn load_config(path: &str) -> Result<ServerConfig, Box<dyn std::error::Error>> {
@@ -14,12 +20,18 @@ pub fn load_config(path: &str) -> Result<ServerConfig, Box<dyn std::error::Error
     Ok(server_config)
 }
 
-pub unsafe fn save_config(path: &str) -> Result<(), Box<dyn std::error::Error>> {
+pub fn load_schemas(path: &str) -> Result<SchemaRegistry, Box<dyn std::error::Error>> {
+    let xml = fs::read_to_string(path)?;
+    let registry: SchemaRegistry = from_str(&xml)?;
+    Ok(registry)
+}
+
+pub fn save_config(path: &str) -> Result<(), Box<dyn std::error::Error>> {
     let mut buffer = String::new();
     let mut serializer = Serializer::new(&mut buffer);
     serializer.indent(' ', 4); // 4 spaces for indentation
 
-    SERVER_CONFIG.serialize(serializer)?;
+    SERVER_CONFIG.get().serialize(serializer)?;
 
     let mut file = File::create(path)?;
     file.write_all(buffer.as_bytes())?;
@@ -27,27 +39,48 @@ pub unsafe fn save_config(path: &str) -> Result<(), Box<dyn std::error::Error>>
     Ok(())
 }
 
-pub fn serialize_bytes_as_base64<S>(bytes: &[u8; 32], serializer: S) -> Result<S::Ok, S::Error>
-where
-    S: serde::Serializer,
-{
-    let encoded = general_purpose::STANDARD.encode(bytes);
-    serializer.serialize_str(&encoded)
+// Reloads config.xml whenever it changes on disk, e.g. edited by hand while running.
+// A file that doesn't parse is logged and ignored, and the running config is kept.
+pub async fn watch_config(path: String, command_tx: UnboundedSender<ServerCommand>) {
+    let modified = |path: &str| fs::metadata(path).and_then(|m| m.modified()).ok();
+    let mut last_modified = modified(&path);
+    let mut interval = tokio::time::interval(Duration::from_millis(CONFIG_POLL_MS));
+    loop {
+        interval.tick().await;
+        let current = modified(&path);
+        if current == last_modified {
+            continue;
+        }
+        last_modified = current;
+
+        let config = match load_config(&path) {
+            Ok(config) => config,
+            Err(e) => {
+                log_error(&format!("{} changed but doesn't parse, keeping the running config: {}", path, e));
+                continue;
+            }
+        };
+        if let Err(e) = check_config(&config) {
+            log_error(&format!("{} changed but can't be used, keeping the running config: {}", path, e));
+            continue;
+        }
+        log(&format!("{} changed, reloading", path));
+        if command_tx.send(ServerCommand::ReloadConfig(config)).is_err() {
+            break; // The server manager has gone
+        }
+    }
 }
 
-pub fn deserialize_bytes_from_base64<'de, D>(deserializer: D) -> Result<[u8; 32], D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let s = <String as Deserialize>::deserialize(deserializer)?;
-    let decoded = STANDARD.decode(s)
-        .map_err(serde::de::Error::custom)?;
-    
-    if decoded.len() != 32 {
-        return Err(serde::de::Error::custom("Invalid byte array length"));
+// Problems a parsed config can still have
+fn check_config(config: &ServerConfig) -> Result<(), String> {
+    for (i, server) in config.server.iter().enumerate() {
+        if let Some(first) = config.server[..i].iter().find(|s| s.id == server.id) {
+            return Err(format!("{} and {} have the same id {}, remove one of their <id> lines", first.name, server.name, server.id));
+        }
+        for area in &server.poll_areas {
+            area.check().map_err(|e| format!("{}: {}", server.name, e))?;
+        }
+        downtime_schema(&server.downtime_schema).map_err(|e| format!("{}: {}", server.name, e))?;
     }
-    
-    let mut array = [0u8; 32];
-    array.copy_from_slice(&decoded);
-    Ok(array)
-}
\ No newline at end of file
+    Ok(())
+}
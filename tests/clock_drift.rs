@@ -0,0 +1,24 @@
+// Comparing PLC clocks with the PC's, without a PLC.
+use chrono::{Duration, Local};
+use s7_event_monitor::clock_drift::{drift_ms, DriftMonitor};
+
+#[test]
+fn drift_is_measured_from_the_middle_of_the_read() {
+    let before = Local::now();
+    let after = before + Duration::milliseconds(200);
+    let plc_time = (before + Duration::milliseconds(1100)).naive_local();
+    assert_eq!(drift_ms(plc_time, before, after), 1000);
+    let plc_time = (before - Duration::milliseconds(400)).naive_local();
+    assert_eq!(drift_ms(plc_time, before, after), -500, "Negative when the PLC is behind");
+}
+
+#[test]
+fn drift_warning_is_raised_once_until_the_clock_recovers() {
+    let mut monitor = DriftMonitor::new(2000);
+    assert!(!monitor.check(1500));
+    assert!(!monitor.check(-2000), "The limit itself is allowed");
+    assert!(monitor.check(-2500));
+    assert!(!monitor.check(3000), "Still out, already warned");
+    assert!(!monitor.check(100));
+    assert!(monitor.check(2500), "Out again after recovering");
+}
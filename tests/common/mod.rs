@@ -0,0 +1,150 @@
+// Runs real servers on ports the OS picks, with the database in a temporary directory,
+// so the tests can talk to them over sockets the way a PLC does.
+// Test binaries that don't start servers use it for init and dints only.
+#![allow(dead_code)]
+use std::sync::{mpsc, Arc, Once};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::{watch, Notify};
+use tokio::task::JoinHandle;
+use s7_event_monitor::comms_tcon::*;
+use s7_event_monitor::comms_s7::S7Session;
+use s7_event_monitor::config_store::{set_config_path, SERVER_CONFIG};
+use s7_event_monitor::event_data::SqlDataPacket;
+use s7_event_monitor::response::{ResponseFormat, LEGACY_ACK, STATUS_RESPONSE_SIZE};
+use s7_event_monitor::simulator::{PacketEncoder, SimulatorConfig};
+use s7_event_monitor::sql::{connect_to_db, query_recent_packets, set_db_path};
+use s7_event_monitor::utils::{set_log_level, set_log_path, LogLevel};
+
+// Longest wait for the server to answer, or for a status to arrive
+const WAIT_SECS: u64 = 5;
+
+static INIT: Once = Once::new();
+
+// Puts the database, config and log in a temporary directory, once per test binary.
+// Every test in a binary shares the database, so each test's server needs its own name.
+pub fn init() {
+    INIT.call_once(|| {
+        let dir = std::env::temp_dir().join(format!("s7-event-monitor-tests-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).expect("Can't create the test directory");
+        set_db_path(&dir.join("event_data.db").to_string_lossy());
+        set_config_path(&dir.join("config.xml").to_string_lossy());
+        set_log_path(&dir.join("log.txt").to_string_lossy());
+        set_log_level(LogLevel::Error);
+        // Create the schema now, before several servers race to
+        connect_to_db().expect("Can't create the test database");
+    });
+}
+
+pub struct TestServer {
+    pub entry: ServerEntry,
+    pub port: u16, // Where it's listening
+    statuses: mpsc::Receiver<ServerStatusInfo>,
+    stop: watch::Sender<bool>,
+    task: JoinHandle<std::io::Result<()>>,
+}
+
+impl TestServer {
+    // Runs `entry` on 127.0.0.1 and a port the OS picks, and waits until it's listening.
+    pub async fn start(mut entry: ServerEntry) -> Self {
+        init();
+        entry.ip_address = "127.0.0.1".to_string();
+        entry.port = 0;
+        SERVER_CONFIG.update(|config| config.server.push(entry.clone()));
+        let (tx, statuses) = mpsc::channel();
+        let (stop, stop_rx) = watch::channel(false);
+        let shutdown = ShutdownSignal::new(Arc::new(Notify::new()), stop_rx);
+        let task = tokio::spawn(run_server(shutdown, entry.id, tx, ServerStatusInfo::new(entry.id), S7Session::new()));
+        let mut server = TestServer { entry, port: 0, statuses, stop, task };
+        server.port = server.wait_for_status(|s| s.listening_port != 0).await.listening_port;
+        server
+    }
+
+    pub async fn connect(&self) -> TcpStream {
+        TcpStream::connect(("127.0.0.1", self.port)).await.expect("Can't connect to the test server")
+    }
+
+    // Builds packets the way this server expects them
+    pub fn encoder(&self) -> PacketEncoder {
+        PacketEncoder::new(&self.simulator_config())
+    }
+
+    // For driving the server with the simulator. Sends no keepalives, so they don't get in the way.
+    pub fn simulator_config(&self) -> SimulatorConfig {
+        let mut config = SimulatorConfig::from_server(&self.entry).expect("The simulator can't talk to this server");
+        config.address = format!("127.0.0.1:{}", self.port);
+        config.keepalive_interval = None;
+        config
+    }
+
+    // Sends one packet's bytes and returns the response
+    pub async fn exchange(&self, socket: &mut TcpStream, bytes: &[u8]) -> Vec<u8> {
+        socket.write_all(bytes).await.expect("Can't send to the test server");
+        self.read_response(socket).await
+    }
+
+    pub async fn read_response(&self, socket: &mut TcpStream) -> Vec<u8> {
+        let mut response = vec![0u8; self.response_size()];
+        tokio::time::timeout(Duration::from_secs(WAIT_SECS), socket.read_exact(&mut response))
+            .await
+            .expect("No response from the test server")
+            .expect("Connection to the test server closed");
+        response
+    }
+
+    pub fn response_size(&self) -> usize {
+        match self.entry.response_format {
+            ResponseFormat::Legacy => LEGACY_ACK.len(),
+            ResponseFormat::Status => STATUS_RESPONSE_SIZE,
+        }
+    }
+
+    // The first status sent since the last call that matches, skipping the rest
+    pub async fn wait_for_status(&mut self, matches: impl Fn(&ServerStatusInfo) -> bool) -> ServerStatusInfo {
+        let deadline = Instant::now() + Duration::from_secs(WAIT_SECS);
+        loop {
+            while let Ok(status) = self.statuses.try_recv() {
+                if matches(&status) {
+                    return status;
+                }
+            }
+            if self.task.is_finished() {
+                panic!("{} stopped before sending the status", self.entry.name);
+            }
+            if Instant::now() > deadline {
+                panic!("{} never sent the status", self.entry.name);
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    // Everything stored for this server, oldest first
+    pub fn stored(&self) -> Vec<SqlDataPacket> {
+        let conn = connect_to_db().expect("Can't open the test database");
+        let mut packets: Vec<SqlDataPacket> = query_recent_packets(&conn, 10_000)
+            .expect("Can't query the test database")
+            .into_iter()
+            .filter(|p| p.plc == self.entry.name)
+            .collect();
+        packets.reverse();
+        packets
+    }
+
+    pub fn stored_codes(&self) -> Vec<u32> {
+        self.stored().iter().map(|p| p.packet.plc_packet_code).collect()
+    }
+
+    pub async fn stop(self) {
+        let _ = self.stop.send(true);
+        let result = tokio::time::timeout(Duration::from_secs(WAIT_SECS), self.task).await;
+        SERVER_CONFIG.update(|config| config.server.retain(|s| s.id != self.entry.id));
+        result.expect("Test server didn't stop").unwrap().unwrap();
+    }
+}
+
+// The DINTs of a packet, for packets the encoder won't build
+pub fn dints(values: &[u32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_be_bytes()).collect()
+}
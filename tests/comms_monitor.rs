@@ -0,0 +1,23 @@
+// Deciding when comms with a PLC are lost and restored, without a PLC.
+use std::time::Duration;
+use s7_event_monitor::comms_monitor::CommsMonitor;
+
+#[test]
+fn a_plc_that_never_connected_isnt_lost() {
+    let mut monitor = CommsMonitor::default();
+    std::thread::sleep(Duration::from_millis(20));
+    assert_eq!(monitor.check(Duration::from_millis(10)), None);
+    assert_eq!(monitor.packet_received(), None, "The first packet isn't a restore");
+}
+
+#[test]
+fn outage_is_reported_once_when_it_starts_and_once_when_it_ends() {
+    let mut monitor = CommsMonitor::default();
+    let timeout = Duration::from_millis(10);
+    monitor.packet_received();
+    std::thread::sleep(Duration::from_millis(20));
+    assert!(monitor.check(timeout).is_some());
+    assert_eq!(monitor.check(timeout), None, "Already reported");
+    assert!(monitor.packet_received().is_some_and(|silent| silent >= timeout));
+    assert_eq!(monitor.packet_received(), None);
+}
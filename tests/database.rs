@@ -0,0 +1,9 @@
+// Opening the database, in its own process so the path can't affect other tests.
+use s7_event_monitor::sql::{connect_to_db, set_db_path};
+
+#[test]
+fn unopenable_database_is_an_error() {
+    let dir = std::env::temp_dir().join(format!("s7-event-monitor-missing-{}", std::process::id()));
+    set_db_path(&dir.join("no_such_dir").join("event_data.db").to_string_lossy());
+    assert!(connect_to_db().is_err());
+}
@@ -0,0 +1,64 @@
+// Reassembling packets from TCP reads, without a socket.
+use s7_event_monitor::framing::{FrameDecoder, FramingMode, MAX_PACKET_SIZE};
+
+fn length_prefixed(payload: &[u8]) -> Vec<u8> {
+    let mut bytes = (payload.len() as u32).to_be_bytes().to_vec();
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+#[test]
+fn length_prefix_split_across_reads() {
+    let mut decoder = FrameDecoder::new(FramingMode::LengthPrefixed, 0);
+    let bytes = length_prefixed(&[1, 2, 3, 4, 5, 6, 7, 8]);
+
+    decoder.push(&bytes[..2]);
+    assert_eq!(decoder.next_frame(), Ok(None), "Half a length header");
+    decoder.push(&bytes[2..6]);
+    assert_eq!(decoder.next_frame(), Ok(None), "Header but not the whole payload");
+    assert_eq!(decoder.pending(), 6);
+    decoder.push(&bytes[6..]);
+    assert_eq!(decoder.next_frame(), Ok(Some(vec![1, 2, 3, 4, 5, 6, 7, 8])));
+    assert_eq!(decoder.pending(), 0);
+}
+
+#[test]
+fn two_length_prefixed_packets_in_one_read() {
+    let mut decoder = FrameDecoder::new(FramingMode::LengthPrefixed, 0);
+    let mut bytes = length_prefixed(&[1, 2, 3, 4]);
+    bytes.extend(length_prefixed(&[5, 6, 7, 8]));
+
+    decoder.push(&bytes);
+    assert_eq!(decoder.next_frame(), Ok(Some(vec![1, 2, 3, 4])));
+    assert_eq!(decoder.next_frame(), Ok(Some(vec![5, 6, 7, 8])));
+    assert_eq!(decoder.next_frame(), Ok(None));
+}
+
+#[test]
+fn oversize_or_zero_length_is_an_error() {
+    let mut decoder = FrameDecoder::new(FramingMode::LengthPrefixed, 0);
+    decoder.push(&((MAX_PACKET_SIZE + 1) as u32).to_be_bytes());
+    assert!(decoder.next_frame().is_err());
+    assert_eq!(decoder.pending(), 0, "Nothing left to resynchronise on");
+
+    decoder.push(&0u32.to_be_bytes());
+    assert!(decoder.next_frame().is_err());
+}
+
+#[test]
+fn fixed_record_remainder_waits_for_the_rest() {
+    let mut decoder = FrameDecoder::new(FramingMode::FixedRecord, 4);
+    decoder.push(&[1, 2, 3, 4, 5, 6]);
+    assert_eq!(decoder.next_frame(), Ok(Some(vec![1, 2, 3, 4])));
+    assert_eq!(decoder.next_frame(), Ok(None));
+    assert_eq!(decoder.pending(), 2);
+    decoder.push(&[7, 8]);
+    assert_eq!(decoder.next_frame(), Ok(Some(vec![5, 6, 7, 8])));
+}
+
+#[test]
+fn fixed_record_without_a_length_is_an_error() {
+    let mut decoder = FrameDecoder::new(FramingMode::FixedRecord, 0);
+    decoder.push(&[1, 2, 3, 4]);
+    assert!(decoder.next_frame().is_err());
+}
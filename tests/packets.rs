@@ -0,0 +1,222 @@
+// Parsing packets and pairing downtime events, without a server.
+mod common;
+
+use chrono::Local;
+use s7_event_monitor::comms_s7::{ChangeDetector, PollArea, SnapshotArea, SnapshotTrigger};
+use s7_event_monitor::constants::*;
+use s7_event_monitor::downtime::process_downtime_packets;
+use s7_event_monitor::event_data::*;
+use s7_event_monitor::payload_schema::{FieldSchema, PayloadSchema, PlcType};
+use s7_event_monitor::plc_time::TimestampFormat;
+use common::dints;
+
+// Parsing loads schemas.xml and logs what it found, so it goes through here to keep the log
+// out of the working directory
+fn parse(bytes: &[u8], layout: &PacketLayout) -> Option<EventDataPacket> {
+    common::init();
+    parse_event_data_packet(bytes, layout)
+}
+
+fn stored(code: u32, timestamp: &str) -> SqlDataPacket {
+    SqlDataPacket {
+        id: 0,
+        query: String::new(),
+        plc: "Edger".to_string(),
+        timestamp: timestamp.to_string(),
+        plc_timestamp: None,
+        packet: EventDataPacket::internal(code, vec![]),
+    }
+}
+
+#[test]
+fn parses_type_code_and_data() {
+    let packet = parse(&dints(&[EVENT_TYPE_PLC, 7, 1, 2]), &PacketLayout::default()).unwrap();
+    assert_eq!(packet.data_type, EVENT_TYPE_PLC);
+    assert_eq!(packet.plc_packet_code, 7);
+    assert_eq!(packet.sequence, None);
+    assert_eq!(packet.data, vec![1, 2]);
+}
+
+#[test]
+fn parses_the_sequence_after_the_code() {
+    let layout = PacketLayout { sequence: true, timestamp: TimestampFormat::None };
+    let packet = parse(&dints(&[EVENT_TYPE_PLC, 7, 99, 1]), &layout).unwrap();
+    assert_eq!(packet.sequence, Some(99));
+    assert_eq!(packet.data, vec![1]);
+}
+
+#[test]
+fn rejects_short_and_misaligned_packets() {
+    let layout = PacketLayout::default();
+    assert!(parse(&[0, 0, 0, 50, 0, 0], &layout).is_none());
+    let mut misaligned = dints(&[EVENT_TYPE_PLC, 7]);
+    misaligned.push(1);
+    assert!(parse(&misaligned, &layout).is_none());
+    let sequenced = PacketLayout { sequence: true, timestamp: TimestampFormat::None };
+    assert!(parse(&dints(&[EVENT_TYPE_PLC, 7]), &sequenced).is_none());
+}
+
+#[test]
+fn downtime_pairs_start_with_the_next_end() {
+    let packets = vec![
+        stored(PACKET_CODE_DOWNTIME_START, "2026-03-02T08:00:00+00:00"),
+        stored(PACKET_CODE_DOWNTIME_END, "2026-03-02T08:05:00+00:00"),
+        stored(PACKET_CODE_DOWNTIME_END, "2026-03-02T08:06:00+00:00"), // No start, ignored
+        stored(PACKET_CODE_DOWNTIME_START, "2026-03-02T09:00:00+00:00"),
+        stored(PACKET_CODE_DOWNTIME_END, "2026-03-02T09:00:30+00:00"),
+    ];
+
+    let records = process_downtime_packets(packets, 120);
+
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].duration, 300 + 120);
+    assert_eq!(records[1].start, "2026-03-02T09:00:00+00:00");
+    assert_eq!(records[1].duration, 30 + 120);
+}
+
+#[test]
+fn downtime_without_an_end_makes_no_record() {
+    let packets = vec![stored(PACKET_CODE_DOWNTIME_START, "2026-03-02T08:00:00+00:00")];
+    assert!(process_downtime_packets(packets, 120).is_empty());
+}
+
+#[test]
+fn poll_area_frames_like_a_tsend() {
+    let area = PollArea { db: 10, start: 0, size: 8, data_type: EVENT_TYPE_ALARM, plc_packet_code: 17 };
+    let packet = parse(&area.frame(&dints(&[3, 4])), &PacketLayout::default()).unwrap();
+    assert_eq!(packet.data_type, EVENT_TYPE_ALARM);
+    assert_eq!(packet.plc_packet_code, 17);
+    assert_eq!(packet.data, vec![3, 4]);
+}
+
+#[test]
+fn poll_area_stored_only_when_it_changes() {
+    let mut changes = ChangeDetector::new(2);
+    assert!(changes.changed(0, &[0, 0, 0, 1]), "The first read records the starting values");
+    assert!(!changes.changed(0, &[0, 0, 0, 1]));
+    assert!(changes.changed(0, &[0, 0, 0, 2]));
+    assert!(changes.changed(1, &[0, 0, 0, 2]), "Areas are tracked separately");
+    assert!(!changes.changed(1, &[0, 0, 0, 2]));
+}
+
+#[test]
+fn poll_area_change_that_wasnt_stored_is_tried_again() {
+    let mut changes = ChangeDetector::new(1);
+    assert!(changes.changed(0, &[0, 0, 0, 1]));
+    assert!(changes.changed(0, &[0, 0, 0, 2]));
+    changes.forget(0); // The database refused it
+    assert!(changes.changed(0, &[0, 0, 0, 2]), "Same bytes, but they were never stored");
+    assert!(!changes.changed(0, &[0, 0, 0, 2]));
+}
+
+#[test]
+fn poll_area_must_be_dints_without_a_schema() {
+    common::init(); // Loads schemas.xml
+    let mut area = PollArea { db: 10, start: 0, size: 8, data_type: EVENT_TYPE_PLC, plc_packet_code: 4321 };
+    assert!(area.check().is_ok());
+    area.size = 6;
+    assert!(area.check().is_err());
+    area.size = 0;
+    assert!(area.check().is_err());
+}
+
+#[test]
+fn snapshot_trigger_matches_type_and_code() {
+    let fault = parse(&[dints(&[EVENT_TYPE_ALARM, 17]), dints(&[1])].concat(), &PacketLayout::default()).unwrap();
+    let area = SnapshotArea { db: 20, start: 0, size: 16, schema: String::new() };
+    let mut trigger = SnapshotTrigger { data_type: EVENT_TYPE_ALARM, plc_packet_code: Some(17), areas: vec![area] };
+    assert!(trigger.matches(&fault));
+    trigger.plc_packet_code = Some(18);
+    assert!(!trigger.matches(&fault));
+    trigger.plc_packet_code = None;
+    assert!(trigger.matches(&fault), "No code matches every code of the type");
+    trigger.data_type = EVENT_TYPE_PLC;
+    assert!(!trigger.matches(&fault));
+}
+
+#[test]
+fn snapshot_area_without_a_known_schema_stores_bytes_only() {
+    common::init(); // Loads schemas.xml
+    let mut area = SnapshotArea { db: 20, start: 0, size: 8, schema: String::new() };
+    assert!(area.decode(&dints(&[1, 2])).is_empty());
+    area.schema = "no such schema".to_string();
+    assert!(area.decode(&dints(&[1, 2])).is_empty());
+}
+
+fn snapshot(in_downtime: bool, current_downtime_secs: u32) -> DowntimeSnapshot {
+    DowntimeSnapshot { in_downtime, reason_code: 7, downtime_count: 3, total_downtime_secs: 600, current_downtime_secs }
+}
+
+#[test]
+fn downtime_db_is_five_dints() {
+    let parsed = DowntimeSnapshot::parse(&dints(&[1, 7, 3, 600, 45])).unwrap();
+    assert_eq!(parsed, snapshot(true, 45));
+    assert!(DowntimeSnapshot::parse(&dints(&[1, 7, 3, 600])).is_none());
+}
+
+#[test]
+fn downtime_db_can_be_described_by_a_schema() {
+    let field = |name: &str, plc_type, byte| FieldSchema { name: name.to_string(), plc_type, byte, bit: 0, scale: 1.0, unit: String::new() };
+    let mut schema = PayloadSchema {
+        name: "Line 2 downtime".to_string(),
+        data_type: 0,
+        plc_packet_code: None,
+        fields: vec![
+            field("InDowntime", PlcType::Bool, 0),
+            field("ReasonCode", PlcType::Int, 2),
+            field("DowntimeCount", PlcType::Dint, 4),
+            field("TotalDowntimeSeconds", PlcType::Dint, 8),
+            field("CurrentDowntimeSeconds", PlcType::Dint, 12),
+        ],
+    };
+    assert_eq!(schema.size(), 16);
+    let mut bytes = vec![1, 0, 0, 7];
+    bytes.extend(dints(&[3, 600, 45]));
+    assert_eq!(DowntimeSnapshot::decode(&bytes, Some(&schema)), Some(snapshot(true, 45)));
+    assert!(DowntimeSnapshot::check_schema(&schema).is_ok());
+
+    schema.fields[4].scale = 0.1;
+    assert_eq!(DowntimeSnapshot::decode(&bytes, Some(&schema)), None, "4.5 seconds isn't a count of seconds");
+    schema.fields[4].scale = 1.0;
+    let mut negative = bytes.clone();
+    negative[12..16].copy_from_slice(&(-1i32).to_be_bytes());
+    assert_eq!(DowntimeSnapshot::decode(&negative, Some(&schema)), None);
+
+    schema.fields.pop();
+    assert!(DowntimeSnapshot::check_schema(&schema).is_err_and(|e| e.contains("CurrentDowntimeSeconds")));
+    assert_eq!(DowntimeSnapshot::decode(&bytes, Some(&schema)), None);
+}
+
+#[test]
+fn downtime_db_that_changed_between_reads_isnt_settled() {
+    assert!(snapshot(true, 45).settled(&snapshot(true, 55)));
+    assert!(!snapshot(true, 45).settled(&snapshot(false, 0)));
+    let next_downtime = DowntimeSnapshot { downtime_count: 4, ..snapshot(true, 5) };
+    assert!(!snapshot(true, 45).settled(&next_downtime), "Ended and started again in between");
+}
+
+#[test]
+fn downtime_db_fills_in_a_missing_start() {
+    let now = Local::now();
+    for last_stored in [None, Some(PACKET_CODE_DOWNTIME_END)] {
+        let event = snapshot(true, 45).missing_event(last_stored, now).unwrap();
+        assert_eq!(event.plc_packet_code, PACKET_CODE_DOWNTIME_START);
+        assert_eq!(event.data, vec![7]);
+        assert_eq!(event.plc_timestamp, Some(now - chrono::Duration::seconds(45)));
+    }
+}
+
+#[test]
+fn downtime_db_fills_in_a_missing_end() {
+    let event = snapshot(false, 0).missing_event(Some(PACKET_CODE_DOWNTIME_START), Local::now()).unwrap();
+    assert_eq!(event.plc_packet_code, PACKET_CODE_DOWNTIME_END);
+    assert_eq!(event.plc_timestamp, None); // Stored as ending when the DB was read
+}
+
+#[test]
+fn downtime_db_that_agrees_fills_in_nothing() {
+    let now = Local::now();
+    assert!(snapshot(true, 45).missing_event(Some(PACKET_CODE_DOWNTIME_START), now).is_none());
+    assert!(snapshot(false, 0).missing_event(Some(PACKET_CODE_DOWNTIME_END), now).is_none());
+    assert!(snapshot(false, 0).missing_event(None, now).is_none());
+}
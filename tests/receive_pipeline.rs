@@ -0,0 +1,314 @@
+// Packets in over real sockets, and what gets stored, answered and reported to the UI.
+mod common;
+
+use chrono::Local;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UdpSocket;
+use s7_event_monitor::comms_tcon::{ServerEntry, Transport};
+use s7_event_monitor::constants::*;
+use s7_event_monitor::downtime::process_downtime_packets;
+use s7_event_monitor::event_data::PlcRuntimeConfig;
+use s7_event_monitor::framing::FramingMode;
+use s7_event_monitor::plc_time::TimestampFormat;
+use s7_event_monitor::response::*;
+use s7_event_monitor::simulator::{ScriptScenario, SimPacket, Simulator};
+use s7_event_monitor::sql::{connect_to_db, latest_plc_config, query_event_snapshots, query_packets, store_event_snapshot};
+use common::{dints, TestServer};
+
+fn server(name: &str) -> ServerEntry {
+    ServerEntry {
+        name: format!("Pipeline test {}", name),
+        ..Default::default()
+    }
+}
+
+fn event(code: u32, data: &[u32]) -> SimPacket {
+    SimPacket::Event { code, data: data.to_vec() }
+}
+
+#[tokio::test]
+async fn event_is_stored_and_acked() {
+    let mut server = TestServer::start(server("Edger")).await;
+    let mut socket = server.connect().await;
+    let mut encoder = server.encoder();
+
+    let response = server.exchange(&mut socket, &encoder.encode(&event(7, &[1, 2, 3])).unwrap()).await;
+
+    assert_eq!(response, LEGACY_ACK);
+    let stored = server.stored();
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].packet.data_type, EVENT_TYPE_PLC);
+    assert_eq!(stored[0].packet.plc_packet_code, 7);
+    assert_eq!(stored[0].packet.data, vec![1, 2, 3]);
+    server.wait_for_status(|s| s.stats.stored == 1).await;
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn keepalives_are_acked_but_not_stored() {
+    let server = TestServer::start(server("Keepalive")).await;
+    let mut socket = server.connect().await;
+    let mut encoder = server.encoder();
+
+    let response = server.exchange(&mut socket, &encoder.encode(&SimPacket::Keepalive).unwrap()).await;
+
+    assert_eq!(response, LEGACY_ACK);
+    assert!(server.stored().is_empty());
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn status_response_echoes_the_sequence_and_drops_duplicates() {
+    let server = TestServer::start(ServerEntry {
+        sequence_numbers: true,
+        response_format: ResponseFormat::Status,
+        ..server("Sequence")
+    }).await;
+    let mut socket = server.connect().await;
+    let mut encoder = server.encoder();
+    let first = encoder.encode(&event(7, &[42])).unwrap();
+    let second = encoder.encode(&event(8, &[43])).unwrap();
+
+    let first_response = server.exchange(&mut socket, &first).await;
+    let second_response = server.exchange(&mut socket, &second).await;
+    let resent_response = server.exchange(&mut socket, &second).await; // Sequence 1 again would look like a PLC restart
+
+    assert_eq!(first_response, encode_response(ResponseFormat::Status, PacketStatus::Stored, Some(1), 7));
+    assert_eq!(second_response, encode_response(ResponseFormat::Status, PacketStatus::Stored, Some(2), 8));
+    assert_eq!(resent_response, encode_response(ResponseFormat::Status, PacketStatus::Duplicate, Some(2), 8));
+    assert_eq!(server.stored_codes(), vec![7, 8]);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn sequence_gap_stores_a_data_loss_event() {
+    let mut server = TestServer::start(ServerEntry {
+        sequence_numbers: true,
+        ..server("Data loss")
+    }).await;
+    let mut socket = server.connect().await;
+
+    server.exchange(&mut socket, &dints(&[EVENT_TYPE_PLC, 7, 1])).await;
+    server.exchange(&mut socket, &dints(&[EVENT_TYPE_PLC, 8, 4])).await;
+
+    let stored = server.stored();
+    let codes: Vec<u32> = stored.iter().map(|p| p.packet.plc_packet_code).collect();
+    assert_eq!(codes, vec![7, 8, PACKET_CODE_DATA_LOSS], "The loss is stored once the packet that showed it is");
+    assert_eq!(stored[2].packet.data, vec![2, 4, 2]); // Expected, received, missing
+    let status = server.wait_for_status(|s| s.lost_packets > 0).await;
+    assert_eq!(status.lost_packets, 2);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn unparseable_packet_gets_a_parse_error() {
+    let server = TestServer::start(ServerEntry {
+        response_format: ResponseFormat::Status,
+        ..server("Parse error")
+    }).await;
+    let mut socket = server.connect().await;
+
+    let response = server.exchange(&mut socket, &[0, 0, 0, 50, 0, 0]).await;
+
+    assert_eq!(response, encode_response(ResponseFormat::Status, PacketStatus::ParseError, None, 0));
+    assert!(server.stored().is_empty());
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn length_prefixed_packet_split_across_reads() {
+    let server = TestServer::start(ServerEntry {
+        framing: FramingMode::LengthPrefixed,
+        ..server("Length prefixed")
+    }).await;
+    let mut socket = server.connect().await;
+    let packet = server.encoder().encode(&event(7, &[1, 2])).unwrap();
+
+    let (first, rest) = packet.split_at(6);
+    socket.write_all(first).await.unwrap();
+    socket.flush().await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    let response = server.exchange(&mut socket, rest).await;
+
+    assert_eq!(response, LEGACY_ACK);
+    assert_eq!(server.stored()[0].packet.data, vec![1, 2]);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn packet_that_cant_be_stored_leaves_the_sequence_as_it_was() {
+    let mut server = TestServer::start(ServerEntry {
+        sequence_numbers: true,
+        response_format: ResponseFormat::Status,
+        ..server("Db error")
+    }).await;
+    let mut socket = server.connect().await;
+    let conn = connect_to_db().unwrap();
+    // Refuses this server's rows only, the other tests share the database
+    let refuse = format!("CREATE TRIGGER refuse_db_error_test BEFORE INSERT ON event_data WHEN NEW.plc = '{}' \
+                          BEGIN SELECT RAISE(ABORT, 'refused by test'); END", server.entry.name);
+
+    server.exchange(&mut socket, &dints(&[EVENT_TYPE_PLC, 7, 1])).await;
+    conn.execute_batch(&refuse).unwrap();
+    let refused = server.exchange(&mut socket, &dints(&[EVENT_TYPE_PLC, 8, 4])).await;
+    conn.execute_batch("DROP TRIGGER refuse_db_error_test").unwrap();
+    let resent = server.exchange(&mut socket, &dints(&[EVENT_TYPE_PLC, 8, 4])).await;
+
+    assert_eq!(refused, encode_response(ResponseFormat::Status, PacketStatus::DbError, Some(4), 8));
+    assert_eq!(resent, encode_response(ResponseFormat::Status, PacketStatus::Stored, Some(4), 8));
+    assert_eq!(server.stored_codes(), vec![7, 8, PACKET_CODE_DATA_LOSS], "No loss stored for the refused packet");
+    let status = server.wait_for_status(|s| s.lost_packets > 0).await;
+    assert_eq!(status.lost_packets, 2, "The gap is counted once");
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn plc_timestamp_is_stored() {
+    let server = TestServer::start(ServerEntry {
+        sequence_numbers: true,
+        plc_timestamps: TimestampFormat::Dtl,
+        ..server("Timestamps")
+    }).await;
+    let mut socket = server.connect().await;
+    let packet = server.encoder().encode(&event(7, &[])).unwrap();
+
+    server.exchange(&mut socket, &packet).await;
+
+    let stored = server.stored();
+    assert_eq!(stored[0].packet.sequence, Some(1));
+    assert!(stored[0].plc_timestamp.is_some());
+    let conn = connect_to_db().unwrap();
+    let today = Local::now().format("%Y-%m-%d").to_string();
+    let queried = query_packets(&conn, &server.entry.name, &today, "", &stored[0].packet.data_type.to_string(), "7").unwrap();
+    assert_eq!(queried[0].packet.sequence, Some(1));
+    assert_eq!(queried[0].packet.plc_timestamp, stored[0].packet.plc_timestamp);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn snapshot_is_linked_to_the_event_that_triggered_it() {
+    let server = TestServer::start(server("Snapshot")).await;
+    let mut socket = server.connect().await;
+    let packet = server.encoder().encode(&event(7, &[1])).unwrap();
+
+    server.exchange(&mut socket, &packet).await;
+
+    let stored = server.stored();
+    let conn = connect_to_db().unwrap();
+    store_event_snapshot(&conn, stored[0].id, 5, 0, &[0, 0, 0, 9], &[], &server.entry.name).unwrap();
+    let snapshots = query_event_snapshots(&conn, stored[0].id).unwrap();
+    assert_eq!(snapshots.len(), 1);
+    assert_eq!(snapshots[0].event_id, stored[0].id);
+    assert_eq!(snapshots[0].data, vec![0, 0, 0, 9]);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn config_packet_is_stored_and_reported() {
+    let mut server = TestServer::start(server("Config")).await;
+    let mut config = server.simulator_config();
+    config.plc_config = PlcRuntimeConfig { downtime_db: 123, seconds_before_downtime: 45 };
+    let mut socket = server.connect().await;
+
+    let packet = s7_event_monitor::simulator::PacketEncoder::new(&config).encode(&SimPacket::Config).unwrap();
+    server.exchange(&mut socket, &packet).await;
+
+    let status = server.wait_for_status(|s| s.plc_config.is_some()).await;
+    assert_eq!(status.plc_config, Some(config.plc_config));
+    let conn = connect_to_db().unwrap();
+    assert_eq!(latest_plc_config(&conn, &server.entry.name).unwrap(), Some(config.plc_config));
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn downtime_start_and_end_make_a_downtime_record() {
+    let server = TestServer::start(server("Downtime")).await;
+    let mut socket = server.connect().await;
+    let mut encoder = server.encoder();
+
+    server.exchange(&mut socket, &encoder.encode(&SimPacket::DowntimeStart).unwrap()).await;
+    server.exchange(&mut socket, &encoder.encode(&SimPacket::DowntimeEnd).unwrap()).await;
+
+    let conn = connect_to_db().unwrap();
+    let today = Local::now().format("%Y-%m-%d").to_string();
+    let packets = query_packets(&conn, &server.entry.name, &today, "", &EVENT_TYPE_SPECIAL.to_string(), "41,42").unwrap();
+    let records = process_downtime_packets(packets, 60);
+    assert_eq!(records.len(), 1);
+    assert!((60..62).contains(&records[0].duration));
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn connection_changes_reach_the_status_channel() {
+    let mut server = TestServer::start(server("Status")).await;
+    let mut socket = server.connect().await;
+    let mut encoder = server.encoder();
+
+    server.wait_for_status(|s| s.is_connected).await;
+    server.exchange(&mut socket, &encoder.encode(&SimPacket::Keepalive).unwrap()).await;
+    let alive = server.wait_for_status(|s| s.is_alive).await;
+    assert_eq!(alive.stats.keepalives, 1);
+    drop(socket);
+    let gone = server.wait_for_status(|s| !s.is_connected).await;
+    assert!(!gone.is_alive);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn stopping_one_server_leaves_the_other_connected() {
+    let edger = TestServer::start(server("Isolation Edger")).await;
+    let trimmer = TestServer::start(server("Isolation Trimmer")).await;
+    let mut edger_socket = edger.connect().await;
+    let mut trimmer_socket = trimmer.connect().await;
+    let keepalive = edger.encoder().encode(&SimPacket::Keepalive).unwrap();
+    assert_eq!(edger.exchange(&mut edger_socket, &keepalive).await, LEGACY_ACK);
+    assert_eq!(trimmer.exchange(&mut trimmer_socket, &keepalive).await, LEGACY_ACK);
+
+    edger.stop().await;
+
+    let _ = edger_socket.write_all(&keepalive).await;
+    let mut response = [0u8; 3];
+    let read = tokio::time::timeout(std::time::Duration::from_secs(2), edger_socket.read(&mut response)).await;
+    assert!(matches!(read, Ok(Ok(0)) | Ok(Err(_))), "Edger's connection should be closed");
+    assert_eq!(trimmer.exchange(&mut trimmer_socket, &keepalive).await, LEGACY_ACK, "Trimmer's connection should still be served");
+    trimmer.stop().await;
+}
+
+#[tokio::test]
+async fn udp_datagram_is_stored_and_acked() {
+    let server = TestServer::start(ServerEntry {
+        transport: Transport::Udp,
+        ..server("UDP")
+    }).await;
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    socket.send_to(&dints(&[EVENT_TYPE_ALARM, 17]), ("127.0.0.1", server.port)).await.unwrap();
+    let mut response = [0u8; 16];
+    let (size, _) = tokio::time::timeout(std::time::Duration::from_secs(5), socket.recv_from(&mut response))
+        .await
+        .expect("No response to the datagram")
+        .unwrap();
+
+    assert_eq!(&response[..size], LEGACY_ACK);
+    assert_eq!(server.stored_codes(), vec![17]);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn simulator_script_is_stored_in_order() {
+    let server = TestServer::start(ServerEntry {
+        sequence_numbers: true,
+        response_format: ResponseFormat::Status,
+        ..server("Simulator")
+    }).await;
+    let mut scenario = ScriptScenario::parse("event 1 100\nalarm 17\ndowntime_start\ndowntime_end\n").unwrap();
+
+    Simulator::new(server.simulator_config()).run(&mut scenario).await;
+
+    assert_eq!(server.stored_codes(), vec![1, 17, PACKET_CODE_DOWNTIME_START, PACKET_CODE_DOWNTIME_END]);
+    let sequences: Vec<Option<u32>> = server.stored().iter().map(|p| p.packet.sequence).collect();
+    assert_eq!(sequences, vec![Some(2), Some(3), Some(4), Some(5)]); // The config packet sent on connect was 1
+    let conn = connect_to_db().unwrap();
+    assert!(latest_plc_config(&conn, &server.entry.name).unwrap().is_some());
+    server.stop().await;
+}
@@ -0,0 +1,50 @@
+// Turning write action values into the bytes written to the PLC, without a PLC.
+use s7_event_monitor::comms_s7::WriteAction;
+use s7_event_monitor::payload_schema::PlcType;
+
+fn action(plc_type: PlcType) -> WriteAction {
+    WriteAction {
+        name: "Reason code".to_string(),
+        db: 30,
+        start: 4,
+        bit: 0,
+        plc_type,
+        value: String::new(),
+        operator_value: true,
+    }
+}
+
+#[test]
+fn numbers_are_written_big_endian() {
+    assert_eq!(action(PlcType::Byte).encode("200", 0).unwrap(), vec![200]);
+    assert_eq!(action(PlcType::Int).encode("-2", 0).unwrap(), vec![0xFF, 0xFE]);
+    assert_eq!(action(PlcType::Dint).encode(" 17 ", 0).unwrap(), vec![0, 0, 0, 17]);
+    assert_eq!(action(PlcType::Real).encode("1.5", 0).unwrap(), 1.5f32.to_be_bytes().to_vec());
+}
+
+#[test]
+fn bool_changes_only_its_bit() {
+    let mut ack = action(PlcType::Bool);
+    ack.bit = 3;
+    assert_eq!(ack.encode("true", 0b1000_0001).unwrap(), vec![0b1000_1001]);
+    assert_eq!(ack.encode("0", 0b1111_1111).unwrap(), vec![0b1111_0111]);
+    assert_eq!(ack.address(), "DB30.DBX4.3");
+}
+
+#[test]
+fn only_an_operator_value_comes_from_the_window() {
+    let mut reset = action(PlcType::Int);
+    reset.value = "0".to_string();
+    assert_eq!(reset.value_to_write("7"), "7");
+    reset.operator_value = false;
+    assert_eq!(reset.value_to_write("7"), "0", "The configured value, whatever was sent");
+}
+
+#[test]
+fn values_that_dont_fit_are_refused() {
+    assert!(action(PlcType::Byte).encode("256", 0).is_err());
+    assert!(action(PlcType::Uint).encode("-1", 0).is_err());
+    assert!(action(PlcType::Dint).encode("", 0).is_err());
+    assert!(action(PlcType::Bool).encode("yes", 0).is_err());
+    assert!(action(PlcType::String).encode("abc", 0).is_err());
+}